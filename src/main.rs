@@ -4,26 +4,56 @@
 // Reference: https://github.com/Narsil/rdev/blob/main/examples/listen.rs
 
 
-use std::{array, sync::{Arc, LazyLock, Mutex}, thread};
+use std::{array, sync::{atomic::{AtomicUsize, Ordering}, Arc, LazyLock, Mutex}, thread};
 
 mod audio_shell;
 mod keyboard_utils;
 mod synth;
 mod ui;
 
-use crate::synth::seq::{SeqStatus, Sequencer};
-use crate::ui::text::{print_info, process_keyboard_events};
-use synth::part::Synth;
+use crate::synth::seq::Sequencer;
+use crate::ui::text::{print_info, process_keyboard_events, transport_loop};
+use synth::part::{Synth, VELOCITY_MAKEUP_GAIN};
 use tinyaudio::{run_output_device, OutputDeviceParameters};
 
 const SAMPLE_RATE: u32 = 48000;
 const BLOCK_SIZE: usize = 2048;
-const MAX_VOICE_COUNT: usize = 4;
+const MAX_VOICE_COUNT: usize = 16;
+const DEFAULT_VOICE_COUNT: usize = 4;
+const SOFT_CLIP_KNEE: f32 = 0.8;
 
-static SELECTED_VOICE: usize = 0;
-pub static VOICES: LazyLock<[Arc<Mutex<Synth<'static>>>; MAX_VOICE_COUNT]> = LazyLock::new(||
+static SELECTED_VOICE: AtomicUsize = AtomicUsize::new(0); // the voice the keyboard plays and edits
+static VOICES: LazyLock<[Arc<Mutex<Synth<'static>>>; MAX_VOICE_COUNT]> = LazyLock::new(||
     array::from_fn(|_| Arc::new(Mutex::new(Synth::new(BLOCK_SIZE))))
 );
+static VOICE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static STARTED_VOICE_COUNT: Mutex<usize> = Mutex::new(0);
+
+/// Voices in use, the first ones of `VOICES`.
+pub fn voices () -> &'static [Arc<Mutex<Synth<'static>>>] {
+    &VOICES[..VOICE_COUNT.load(Ordering::Relaxed)]
+}
+
+/// Changes the number of voices in use, starting up the voices that are used for the first time.
+pub fn set_voice_count (count: usize) {
+    let count = count.clamp(1, MAX_VOICE_COUNT);
+    let mut started = STARTED_VOICE_COUNT.lock().unwrap();
+    for synth in VOICES.iter().take(count).skip(*started) {
+        { synth.lock().unwrap().init();  }
+
+        let synth = synth.clone();
+        thread::spawn(move || {
+            Synth::control_loop(synth);
+        });
+    }
+    *started = count.max(*started);
+    VOICE_COUNT.store(count, Ordering::Relaxed);
+}
+
+/// Voice count asked for on the command line, as in `--voices 8`.
+fn voice_count_arg () -> Option<usize> {
+    std::env::args().skip_while(|arg| arg != "--voices").nth(1)?.parse().ok()
+}
 
 fn main() {
     
@@ -33,10 +63,14 @@ fn main() {
         channel_sample_count: BLOCK_SIZE,
     };
     
+    let seq: Arc<Mutex<Sequencer>> = Arc::new(Sequencer::new(120.).into());
+    set_voice_count(voice_count_arg().unwrap_or(DEFAULT_VOICE_COUNT));
+
+    let seq_audio = seq.clone();
     let _output_device = run_output_device(params, move |data| {
         let mut samples_left = vec![0.0; BLOCK_SIZE];
         let mut samples_right = vec![0.0; BLOCK_SIZE];
-        output_sound(&mut samples_left, &mut samples_right);
+        output_sound(&seq_audio, &mut samples_left, &mut samples_right);
 
         for (frame_no, samples) in data.chunks_mut(params.channels_count).enumerate() {
             samples[0] = samples_left[frame_no];
@@ -44,58 +78,74 @@ fn main() {
         }
     }).unwrap();
 
-    let seq: Arc<Mutex<Sequencer>> = Arc::new(
-        Sequencer {
-            tempo: 120.,
-            status: SeqStatus::Stop,
-        }.into());
-
-    for synth in VOICES.iter() {
-        { synth.lock().unwrap().init();  }
-        
-        let synth_1 = synth.clone();
-        let seq_1 = seq.clone();
-        thread::spawn(move || {
-            Synth::sequencer_loop(synth_1, seq_1);
-        });
-
-        let synth_2 = synth.clone();
-        let seq_2 = seq.clone();
-        thread::spawn(move || {
-            Synth::control_loop(synth_2);
-        });
-    }
 
+    let seq_ui = seq.clone();
+    thread::spawn(move || {
+        transport_loop(seq_ui);
+    });
 
-    print_info(&seq.lock().unwrap(), &VOICES.first().unwrap().lock().unwrap());
+    print_info(&seq.lock().unwrap(), &voices().first().unwrap().lock().unwrap());
 
     let _ = rdev::listen(process_keyboard_events(seq)); // handle keystrokes, blocking
 }
 
 
 
-fn output_sound(samples_l: &mut [f32], samples_r: &mut [f32]) {
-    let mut out = [[0.0; BLOCK_SIZE]; MAX_VOICE_COUNT];
-    let mut aux = [[0.0; BLOCK_SIZE]; MAX_VOICE_COUNT];
+fn output_sound(seq: &Arc<Mutex<Sequencer>>, samples_l: &mut [f32], samples_r: &mut [f32]) {
+    let voices = voices();
+    let mut out = vec![[0.0; BLOCK_SIZE]; voices.len()];
+    let mut aux = vec![[0.0; BLOCK_SIZE]; voices.len()];
 
-    for (i, synth) in VOICES.iter().enumerate() {
+    let mut seq = seq.lock().unwrap();
+    // chord steps are spread over the voices that are not played, either by a pattern of their own or from the keyboard
+    let chords: Vec<_> = voices.iter().flat_map(|synth| synth.lock().unwrap().chord_notes_due(&seq, BLOCK_SIZE)).collect();
+    let selected_voice = SELECTED_VOICE.load(Ordering::Relaxed);
+    let free_voices: Vec<_> = voices.iter().enumerate()
+        .filter(|(i, synth)| *i != selected_voice && synth.lock().unwrap().is_idle())
+        .map(|(_, synth)| synth)
+        .collect();
+    for chord in chords {
+        for (chord_note, synth) in chord.into_iter().zip(free_voices.iter()) {
+            synth.lock().unwrap().play_chord_note(chord_note);
+        }
+    }
+    for (i, synth) in voices.iter().enumerate() {
         let out_i = out.get_mut(i).unwrap();
         let aux_i = aux.get_mut(i).unwrap();
-        synth.lock().unwrap().render(out_i, aux_i);
+        synth.lock().unwrap().render_sequence(&seq, out_i, aux_i);
+    }
+    let mut click = [0.0; BLOCK_SIZE];
+    seq.render_metronome(&mut click);
+    if seq.is_running() {
+        seq.advance(BLOCK_SIZE);
     }
 
-    for (i, synth) in VOICES.iter().enumerate() {
+    let is_soloing = voices.iter().any(|synth| synth.lock().unwrap().is_soloed);
+    for (i, synth) in voices.iter().enumerate() {
         let v = synth.lock().unwrap();
+        if !v.is_audible(is_soloing) { continue; }
         let out_i = out.get(i).unwrap().to_owned();
-        // let aux_i = aux.get(i).unwrap().to_owned();
+        let aux_i = aux.get(i).unwrap().to_owned();
         let (pan_r, pan_l) = equal_power_panlaw_r_l(v.pan);
         for frame in 0..BLOCK_SIZE {
-            // let sample = (out_i[frame] * (1.0 - v.balance) + aux_i[frame] * v.balance) * v.volume;
-            let sample = out_i[frame] * v.volume;
+            let sample = (out_i[frame] * (1.0 - v.balance) + aux_i[frame] * v.balance) * v.volume * VELOCITY_MAKEUP_GAIN;
             samples_l[frame] += sample * pan_l;
             samples_r[frame] += sample * pan_r;
         }
     }
+    for frame in 0..BLOCK_SIZE {
+        samples_l[frame] = soft_clip(samples_l[frame] + click[frame]);
+        samples_r[frame] = soft_clip(samples_r[frame] + click[frame]);
+    }
+}
+
+/// Leaves the signal as it is up to the knee, then bends it smoothly towards full scale,
+/// as accents and many voices together can go past it.
+fn soft_clip (sample: f32) -> f32 {
+    let excess = sample.abs() - SOFT_CLIP_KNEE;
+    if excess <= 0. { return sample; }
+    let headroom = 1. - SOFT_CLIP_KNEE;
+    sample.signum() * (SOFT_CLIP_KNEE + headroom * (excess / headroom).tanh())
 }
 
 fn equal_power_panlaw_r_l (pan: f32) -> (f32, f32) {
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use crate::synth::scale::{degree2note, note2degree};
+use crate::synth::seq::SeqStep;
+use crate::synth::util::Rng;
+
+/// A note as a scale degree (or a rest) together with how many steps it lasts.
+type Token = (Option<i32>, usize);
+
+/// Splits a pattern into tokens: every note takes the rests that follow it as its duration.
+fn tokenize (steps: &[SeqStep]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    for step in steps {
+        match (step.note, tokens.last_mut()) {
+            (Some(note), _) => tokens.push((Some(note2degree(note)), 1)),
+            (None, Some((_, duration))) => *duration += 1,
+            (None, None) => tokens.push((None, 1)),
+        }
+    }
+    tokens
+}
+
+/// First order Markov chain over scale degrees and durations.
+#[derive(Debug, Default)]
+pub struct Markov {
+    tokens: Vec<Token>,
+    transitions: HashMap<Token, Vec<Token>>,
+}
+
+impl Markov {
+    /// Counts the transitions of every pattern, each one wrapping around as it does when looping.
+    pub fn train (patterns: &[Vec<SeqStep>]) -> Self {
+        let mut markov = Self::default();
+        for tokens in patterns.iter().map(|steps| tokenize(steps)) {
+            for (i, &token) in tokens.iter().enumerate() {
+                let next = tokens[(i + 1) % tokens.len()];
+                markov.transitions.entry(token).or_default().push(next);
+            }
+            markov.tokens.extend(tokens);
+        }
+        markov
+    }
+
+    /// Walks the chain from a random token until the pattern is `length` steps long.
+    pub fn generate (&self, length: usize, rng: &mut Rng) -> Vec<SeqStep> {
+        let mut steps = Vec::with_capacity(length);
+        if self.tokens.is_empty() { return steps; }
+        let mut token = self.tokens[rng.below(self.tokens.len())];
+        while steps.len() < length {
+            let (degree, duration) = token;
+            steps.push(SeqStep { note: degree.map(degree2note), ..Default::default() });
+            steps.extend(std::iter::repeat_n(SeqStep::default(), duration - 1));
+            let next = &self.transitions[&token];
+            token = next[rng.below(next.len())];
+        }
+        steps.truncate(length);
+        steps
+    }
+}
+
+const TURING_MIN_LENGTH: usize = 2;
+const TURING_MAX_LENGTH: usize = 16;
+const TURING_RANGE: usize = 30; // two octaves of scale degrees
+
+/// Looping random sequence in the manner of the Turing Machine module. The pattern is the shift register:
+/// every time a step comes round it is replaced by a random degree, unless the lock holds it.
+#[derive(Debug)]
+pub struct TuringMachine {
+    pub lock: f32,
+    pub length: usize,
+}
+
+impl TuringMachine {
+    fn random_step (rng: &mut Rng) -> SeqStep {
+        SeqStep { note: Some(degree2note(rng.below(TURING_RANGE) as i32)), ..Default::default() }
+    }
+
+    /// Brings the register to its length, filling new steps with random degrees.
+    pub fn fill (&self, steps: &mut Vec<SeqStep>, rng: &mut Rng) {
+        steps.truncate(self.length);
+        while steps.len() < self.length {
+            steps.push(Self::random_step(rng));
+        }
+    }
+
+    pub fn shift (&self, step: &mut SeqStep, rng: &mut Rng) {
+        if rng.unit() >= self.lock {
+            step.note = Self::random_step(rng).note;
+        }
+    }
+
+    pub fn length_up   (&mut self) { self.length = (self.length + 1).min(TURING_MAX_LENGTH); }
+    pub fn length_down (&mut self) { self.length = (self.length - 1).max(TURING_MIN_LENGTH); }
+}
@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const HISTORY_DEPTH: usize = 100;
+
+// numbers the commands of all histories, so that separate histories can be undone in the order the commands ran
+static COMMANDS: AtomicU64 = AtomicU64::new(0);
+
+/// Undo and redo stacks of whole snapshots. A command is kept only if it changed something.
+#[derive(Debug)]
+pub struct History<T> {
+    undo: Vec<(u64, T)>,
+    redo: Vec<(u64, T)>,
+}
+
+impl<T: PartialEq> History<T> {
+    pub fn new () -> Self {
+        Self { undo: Vec::new(), redo: Vec::new() }
+    }
+
+    /// Keeps `before` as an undo step, unless it is the same as `after`. A new command drops the redo steps.
+    pub fn commit (&mut self, before: T, after: &T) {
+        if before == *after { return; }
+        if self.undo.len() == HISTORY_DEPTH { self.undo.remove(0); }
+        self.undo.push((COMMANDS.fetch_add(1, Ordering::Relaxed), before));
+        self.redo.clear();
+    }
+
+    /// Swaps `current` for the last undo step, which is returned to be restored.
+    pub fn undo (&mut self, current: T) -> Option<T> {
+        let (command, previous) = self.undo.pop()?;
+        self.redo.push((command, current));
+        Some(previous)
+    }
+
+    pub fn redo (&mut self, current: T) -> Option<T> {
+        let (command, next) = self.redo.pop()?;
+        self.undo.push((command, current));
+        Some(next)
+    }
+
+    /// Number of the command the next undo takes back.
+    pub fn undo_command (&self) -> Option<u64> { self.undo.last().map(|(command, _)| *command) }
+    /// Number of the command the next redo brings back.
+    pub fn redo_command (&self) -> Option<u64> { self.redo.last().map(|(command, _)| *command) }
+
+    pub fn can_undo (&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo (&self) -> bool { !self.redo.is_empty() }
+}
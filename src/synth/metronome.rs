@@ -0,0 +1,39 @@
+use crate::synth::seq::{Clock, PPQN};
+use crate::SAMPLE_RATE;
+
+const CLICK_FREQUENCY: f32 = 1000.;
+const ACCENT_FREQUENCY: f32 = 1500.;
+const CLICK_LEVEL: f32 = 0.2;
+const ACCENT_LEVEL: f32 = 0.4;
+const CLICK_DECAY: f32 = 0.015; // seconds
+
+/// Sine blips on every beat, higher and louder on the first beat of the bar.
+#[derive(Debug, Default)]
+pub struct Metronome {
+    pub is_on: bool,
+    phase: f32,
+    frequency: f32,
+    level: f32,
+}
+
+impl Metronome {
+    /// Adds the block starting at `clock` to `out`. New clicks start only if `is_counting`, ringing ones always decay.
+    pub fn render (&mut self, clock: &Clock, tempo: f32, is_counting: bool, out: &mut [f32]) {
+        let ticks_per_sample = Clock::ticks_per_sample(tempo);
+        let decay = (-1. / (CLICK_DECAY * SAMPLE_RATE as f32)).exp();
+        for (frame, sample) in out.iter_mut().enumerate() {
+            let tick = clock.tick + frame as f64 * ticks_per_sample;
+            let beat = (tick / PPQN as f64).floor();
+            if is_counting && tick >= 0. && beat != ((tick - ticks_per_sample) / PPQN as f64).floor() {
+                let is_downbeat = (beat as u32).is_multiple_of(clock.beats_per_bar);
+                self.frequency = if is_downbeat { ACCENT_FREQUENCY } else { CLICK_FREQUENCY };
+                self.level = if is_downbeat { ACCENT_LEVEL } else { CLICK_LEVEL };
+                self.phase = 0.;
+            }
+            if self.level < f32::EPSILON { continue; }
+            *sample += self.level * (std::f32::consts::TAU * self.phase).sin();
+            self.phase = (self.phase + self.frequency / SAMPLE_RATE as f32).fract();
+            self.level *= decay;
+        }
+    }
+}
@@ -1,3 +1,10 @@
 pub mod part;
+mod poly;
+mod generate;
+mod history;
+pub mod metronome;
+mod scale;
+mod scene;
 pub mod seq;
+mod transform;
 mod util;
\ No newline at end of file
@@ -2,37 +2,109 @@ use crate::audio_shell::AudioGenerator;
 use mi_plaits_dsp::dsp::voice::{Modulations, Patch, Voice};
 
 use linked_hash_set::LinkedHashSet;
+use std::collections::VecDeque;
 use std::vec;
 
-use spin_sleep;
 use std::mem::transmute;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::synth::seq::{SeqStatus, SeqStep, Sequencer, TRANSPORT};
+use crate::synth::generate::{Markov, TuringMachine};
+use crate::synth::history::History;
+use crate::synth::poly::NotePriority;
+use crate::synth::seq::{Clock, Direction, RecordMode, SeqStatus, SeqStep, Sequencer, TICKS_PER_STEP};
+use crate::synth::scale::note2degree;
+use crate::synth::scene::Scene;
+use crate::synth::transform;
 use crate::synth::util::*;
+use crate::SAMPLE_RATE;
 use num::{Num, NumCast, ToPrimitive};
 
 pub enum Param  {
-    Note, Rest, Model, Harmonic, Timbre, Morph, Decay, GateLength
+    Note, Rest, Model, Harmonic, Timbre, Morph, Decay, GateLength, Glide, Volume, Pan, Balance, LpgColour, Velocity
 }
 
 const BEND_NEUTRAL : f32 = 0.;
 const BEND_POSITIVE: f32 = 4./22. * 12.;
 const BEND_NEGATIVE: f32 = -4./22. * 12.;
 
-const BEND_SMOOTH_FACTOR:     f32 = 0.000_01;
-const BEND_SMOOTH_FACTOR_INV: f32 = 1. - BEND_SMOOTH_FACTOR;
-const VIBRATO_PRESS_SMOOTH_FACTOR:       f32 = 0.000_002;
-const VIBRATO_PRESS_SMOOTH_FACTOR_INV:   f32 = 1. - VIBRATO_PRESS_SMOOTH_FACTOR;
-const VIBRATO_RELEASE_SMOOTH_FACTOR:     f32 = 0.001;
-const VIBRATO_RELEASE_SMOOTH_FACTOR_INV: f32 = 1. - VIBRATO_RELEASE_SMOOTH_FACTOR;
-const PARAM_SMOOTH_FACTOR:     f32 = 0.001;
-const PARAM_SMOOTH_FACTOR_INV: f32 = 1. - PARAM_SMOOTH_FACTOR;
+const BEND_SMOOTH_FACTOR:            f32 = 0.000_01;
+const VIBRATO_PRESS_SMOOTH_FACTOR:   f32 = 0.000_002;
+const VIBRATO_RELEASE_SMOOTH_FACTOR: f32 = 0.001;
+const PARAM_SMOOTH_FACTOR:           f32 = 0.001;
+
+// the smoothing factors above are per microsecond, and are compounded into one step per control period
+const CONTROL_PERIOD: Duration = Duration::from_millis(5);
+const SMOOTHING_STEPS_PER_PERIOD: i32 = 5000;
 
 const VIBRATO_DEPTH: f32 = 0.6;
 const VIBRATO_RATE: f32 = std::f32::consts::PI * 10.;
 
+const DEFAULT_VELOCITY: f32 = 0.8;
+const ACCENT_VELOCITY: f32 = 1.0;
+const VELOCITY_TIMBRE_DEPTH: f32 = 0.5; // brightness added per unit of velocity above the default
+// unaccented notes play below full level, so the output is brought back up to where full level used to put it
+pub const VELOCITY_MAKEUP_GAIN: f32 = 1. / DEFAULT_VELOCITY;
+
+const HUMANIZE_TIMING: f32 = 0.1; // fraction of a step at full humanize
+const HUMANIZE_LEVEL: f32 = 0.25;
+
+const MAX_NUDGE: f64 = 0.5;
+const MIN_GATE_SAMPLES: f64 = 24.; // so that a step nudged late still sounds before the next one, nudged early, comes in
+const NUDGE_STEP: f64 = 0.05;
+
+const LIVE_RECORD_STEPS: usize = 16;
+const LIVE_MIN_GATE_LENGTH: f64 = 0.1;
+
+const SCENE_MORPH_THRESHOLD: f32 = 0.000_1;
+const SCENE_MORPH_TIME: f32 = 2.; // seconds to cover 95% of a move between the scenes
+
+const MIN_GENERATOR_LENGTH: usize = 2;
+const MAX_GENERATOR_LENGTH: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Schedule {
+    is_running: bool,
+    next_step: i64, // counted from the start of the song, not of the pattern
+    gate_off: Option<f64>, // clock tick
+}
+
+/// A note of a chord step, handed over to a voice that has no pattern of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct ChordNote {
+    tick: f64,
+    gate_off: f64,
+    step: SeqStep,
+}
+
+/// What undo brings back: the pattern and sound of a voice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    seq_notes: Vec<SeqStep>,
+    turing_backup: Option<Vec<SeqStep>>, // whether the pattern is a Turing machine register, and the one it stands in for
+    model: usize,
+    harmonic: f32,
+    timbre: f32,
+    morph: f32,
+    decay: f32,
+    lpg_colour: f32,
+    volume: f32,
+    pan: f32,
+    balance: f32,
+    velocity: f32,
+    gate_length: f64,
+    glide_time: f32,
+    slide: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LiveNote {
+    key: i32,
+    step: usize,
+    onset: f64,
+}
+
 #[derive(Debug)]
 pub struct Synth<'a> {
 
@@ -45,8 +117,12 @@ pub struct Synth<'a> {
     pub volume: f32,
     pub pan: f32,
     pub balance: f32,
+    pub velocity: f32, // of the notes played on the keyboard without accent
+    pub is_muted: bool,
+    pub is_soloed: bool,
 
     note: f32,
+    smooth_note: f32,
     transpose: f32,
     rec_transpose: f32,
     pub info_octave: i16,
@@ -56,13 +132,43 @@ pub struct Synth<'a> {
     pub target_morph:f32, smooth_morph:f32,
     target_vibrato_amount: f32, smooth_vibrato_amount: f32,
     target_bend: f32, smooth_bend: f32, 
+    velocity_timbre: f32,
+    pub target_scene_morph: f32, smooth_scene_morph: f32,
+    scenes: [Option<Scene>; 2],
 
     pub tempo: f64,
+    humanize: f32,
+    seed: u64, // for randomness that has to stay the same whenever a step is looked at again
     pub gate_length: f64,
+    pub glide_time: f32,
+    pub slide: bool,
+    is_gliding: bool,
+    active_glide_time: f32,
     pressed_set: LinkedHashSet<i32>,
+    pub note_priority: NotePriority,
+    pub legato: bool, // keys played over a held one glide to their note instead of striking the engine again
+    is_retriggering: bool,
     pub seq_notes: Vec<SeqStep>,
     pub seq_status: SeqStatus,
     first_step_backup: SeqStep,
+
+    pub record_mode: RecordMode,
+    pub direction: Direction,
+    loop_range: Option<(usize, usize)>,
+    pub quantize: f64,
+    schedule: Schedule,
+    chord_notes: VecDeque<ChordNote>,
+    own_sound: Option<SeqStep>, // kept aside while a chord note plays with the sound of another voice
+    playhead: i64, // global step, so that the steps around it can be found through the direction and the loop
+    playhead_time: Instant,
+    live_note: Option<LiveNote>,
+    last_live_step: Option<usize>,
+    pub cursor: Option<usize>,
+    pub generator_length: usize,
+    pub turing: TuringMachine,
+    turing_backup: Option<Vec<SeqStep>>,
+    history: History<Snapshot>,
+    rng: Rng,
 }
 
 impl<'a> Synth<'a> {
@@ -77,21 +183,55 @@ impl<'a> Synth<'a> {
             volume: 1.0,
             balance: 0.0,
             pan: 0.5,
+            velocity: DEFAULT_VELOCITY,
+            is_muted: false,
+            is_soloed: false,
             note: 48.0,
+            smooth_note: 48.0,
             transpose: 48.0,
             rec_transpose: 0.,
             info_octave: 5,
 
             target_harmonic: 0.5, target_timbre: 0.5, target_morph:0.5, target_bend: BEND_NEUTRAL, target_vibrato_amount: 0.,
             smooth_harmonic: 0.5, smooth_timbre: 0.5, smooth_morph:0.5, smooth_bend: BEND_NEUTRAL, smooth_vibrato_amount: 0.,
+            target_scene_morph: 0., smooth_scene_morph: 0.,
+            velocity_timbre: 0.,
+            scenes: [None; 2],
 
             tempo: 120.,
+            humanize: 0.,
+            seed: Rng::new().next_u64(),
             gate_length: 0.5,
+            glide_time: 0.1,
+            slide: false,
+            is_gliding: false,
+            active_glide_time: 0.,
             pressed_set: LinkedHashSet::default(),
+            note_priority: NotePriority::Last,
+            legato: false,
+            is_retriggering: false,
             seq_notes: Vec::default(),
             seq_status: SeqStatus::Stop,
 
             first_step_backup: SeqStep::default(), 
+
+            record_mode: RecordMode::Step,
+            direction: Direction::Forward,
+            loop_range: None,
+            quantize: 1.,
+            schedule: Schedule::default(),
+            chord_notes: VecDeque::new(),
+            own_sound: None,
+            playhead: 0,
+            playhead_time: Instant::now(),
+            live_note: None,
+            last_live_step: None,
+            cursor: None,
+            generator_length: 16,
+            turing: TuringMachine { lock: 0.8, length: 8 },
+            turing_backup: None,
+            history: History::new(),
+            rng: Rng::new(),
         }
     }
 
@@ -99,92 +239,413 @@ impl<'a> Synth<'a> {
         self.synth_engine.init();
     }
 
-    pub fn sequencer_loop (arc_synth: Arc<Mutex<Synth>>, arc_seq: Arc<Mutex<Sequencer>>) {
+    /// Renders one block while playing the pattern, splitting the block wherever a step starts or a gate closes,
+    /// so that events land on the exact sample they are due. Steps follow the sequencer clock, which is shared by all voices.
+    pub fn render_sequence (&mut self, seq: &Sequencer, out: &mut [f32], aux: &mut [f32]) {
+        let block_time = Instant::now();
+        let block_size = out.len();
+        self.follow(seq);
+
+        if !self.is_sequencer_running(seq) {
+            if self.schedule.is_running {
+                if self.schedule.gate_off.is_some() { self.release_step(); }
+                self.schedule = Schedule::default();
+                self.chord_notes.clear();
+            }
+            self.render(out, aux);
+            return;
+        }
+
+        let ticks_per_sample = Clock::ticks_per_sample(seq.tempo);
+        let block_start = seq.clock.tick;
+        let block_end = block_start + block_size as f64 * ticks_per_sample;
+        let tick2frame = |tick: f64| (((tick - block_start) / ticks_per_sample).max(0.).ceil() as usize).min(block_size);
+        self.join_clock(seq);
+
+        let mut frame = self.render_gate_gap(out, aux);
         loop {
-            println!("debug sequencer!!!");
-            let is_playing = TRANSPORT.condvar.wait(
-                TRANSPORT.is_playing.lock().unwrap()
-            ).unwrap().to_owned();
-            if is_playing {
-                let seq_notes = {  arc_synth.lock().unwrap().seq_notes.clone() };
-                'play_loop: loop {
-                    for step in seq_notes.iter() {    
-                        let delta = Instant::now();
-                        let (sec_gate_on, sec_gate_off) = { 
-                            let synth = arc_synth.lock().unwrap();
-                            let gate_length = step.gate_length.unwrap_or(synth.gate_length);
-                            let sec_per_8th = 30. / synth.tempo;
-                            let sec_gate_on = sec_per_8th * gate_length;
-                            let sec_gate_off = sec_per_8th - sec_gate_on;
-                            (sec_gate_on, sec_gate_off)
-                        };
-                        {
-                            let mut synth = arc_synth.lock().unwrap();
-                            if arc_seq.lock().unwrap().is_stopped() {
-                                break 'play_loop;
-                            }
-                            if let Some(note) = step.note {
-                                synth.note = note;
-                                synth.modulations.trigger = 1.;
-                                synth.modulations.level = 1.;
-                            }
-                            if let Some(model) = step.model { synth.patch.engine = model; }
-                            if let Some(harmonic) = step.harmonic { 
-                                synth.smooth_harmonic = harmonic;
-                                synth.target_harmonic = harmonic;
-                            }
-                            if let Some(timbre) = step.timbre { 
-                                synth.smooth_timbre = timbre;
-                                synth.target_timbre = timbre;
-                            }
-                            if let Some(morph) = step.morph { 
-                                synth.smooth_morph = morph;
-                                synth.target_morph = morph;
-                            }
-                            if let Some(decay) = step.decay { 
-                                synth.patch.decay = decay;
-                            }
-                        }
-                        spin_sleep::sleep(Duration::from_secs_f64(sec_gate_on) - delta.elapsed()); 
-                        let delta = Instant::now();
-                        {
-                            let mut synth = arc_synth.lock().unwrap();
-                            synth.modulations.trigger = 0.;
-                            synth.modulations.level = 0.;                    
-                        }
-                        spin_sleep::sleep(Duration::from_secs_f64(sec_gate_off) - delta.elapsed());
-                    }
-                }
+            let next_trigger = Some(self.schedule.next_step)
+                .filter(|_| !self.seq_notes.is_empty())
+                .map(|step| self.step_tick(step))
+                .filter(|&tick| tick < block_end);
+            let next_chord_note = self.chord_notes.front().map(|chord_note| chord_note.tick).filter(|&tick| tick < block_end);
+            let gate_off = self.schedule.gate_off.filter(|&tick| tick < block_end);
+            let next_event = [next_trigger, next_chord_note, gate_off].into_iter().flatten().reduce(f64::min);
+            let next_frame = match next_event { Some(tick) => tick2frame(tick).max(frame), None => block_size };
+            if next_frame > frame {
+                self.synth_engine.render(&self.patch, &self.modulations, &mut out[frame..next_frame], &mut aux[frame..next_frame]);
+                frame = next_frame;
+            }
+            let Some(next_event) = next_event else { break };
+
+            if gate_off.is_some_and(|gate_off| gate_off <= next_event) {
+                self.release_step();
+                self.schedule.gate_off = None;
+                continue;
+            }
+            if next_chord_note.is_some_and(|tick| tick <= next_event) {
+                let chord_note = self.chord_notes.pop_front().unwrap();
+                self.schedule.gate_off = Some(chord_note.gate_off);
+                if self.own_sound.is_none() { self.own_sound = Some(self.sound()); }
+                self.trigger_step(&chord_note.step);
+                continue;
+            }
+            let global_step = self.schedule.next_step;
+            let i = self.pattern_index(global_step);
+            let mut step = self.seq_notes[i];
+            if self.humanize > 0. {
+                let spread = self.humanize * HUMANIZE_LEVEL * jitter(!self.seed, global_step);
+                step.velocity = Some((step.velocity.unwrap_or(DEFAULT_VELOCITY) * (1. + spread)).clamp(0., 1.));
+            }
+            let next_step = self.seq_notes[self.pattern_index(global_step + 1)];
+            let is_tied = step.note.is_some() && next_step.slide && next_step.note.is_some();
+            self.schedule.gate_off = if is_tied { None } else { Some(self.gate_off_tick(global_step, ticks_per_sample)) };
+            self.schedule.next_step = global_step + 1;
+            let grid_tick = global_step as f64 * TICKS_PER_STEP;
+            self.move_playhead(global_step, block_time, (grid_tick - block_start) / ticks_per_sample / SAMPLE_RATE as f64);
+            if !step.condition.is_met(seq.is_filling) { continue; }
+            self.trigger_step(&step);
+            if self.is_turing_running() {
+                self.turing.shift(&mut self.seq_notes[i], &mut self.rng);
+            }
+        }
+    }
+
+    /// Takes over the transport state that all voices share.
+    fn follow (&mut self, seq: &Sequencer) {
+        self.tempo = seq.tempo as f64;
+        self.seq_status = seq.status;
+        self.humanize = seq.humanize;
+    }
+
+    /// (Re)joins the clock when starting, or when it has been moved away from the step we were waiting for.
+    fn join_clock (&mut self, seq: &Sequencer) {
+        let clock_step = (seq.clock.tick / TICKS_PER_STEP).ceil() as i64;
+        if !self.schedule.is_running || (self.schedule.next_step - clock_step).abs() > 1 {
+            self.schedule.next_step = clock_step;
+        }
+        self.schedule.is_running = true;
+    }
+
+    fn gate_off_tick (&self, global_step: i64, ticks_per_sample: f64) -> f64 {
+        let i = self.pattern_index(global_step);
+        let gate_ticks = TICKS_PER_STEP * self.seq_notes[i].gate_length.unwrap_or(self.gate_length);
+        // closing the gate at least a sample before the next trigger lets the engine see a new rising edge
+        let gate_off_limit = self.step_tick(global_step + 1) - ticks_per_sample;
+        let step_tick = self.step_tick(global_step);
+        (step_tick + gate_ticks).min(gate_off_limit).max(step_tick + MIN_GATE_SAMPLES * ticks_per_sample)
+    }
+
+    /// Extra notes of the chord steps due within the coming block, one chord per step, to be played by free voices.
+    /// Each note takes the sound of this voice, so the chord sounds as one.
+    pub fn chord_notes_due (&mut self, seq: &Sequencer, block_size: usize) -> Vec<Vec<ChordNote>> {
+        let mut chords = Vec::new();
+        self.follow(seq);
+        if !self.is_sequencer_running(seq) || self.seq_notes.is_empty() { return chords; }
+        self.join_clock(seq);
+        let ticks_per_sample = Clock::ticks_per_sample(seq.tempo);
+        let block_end = seq.clock.tick + block_size as f64 * ticks_per_sample;
+        let mut global_step = self.schedule.next_step;
+        while self.step_tick(global_step) < block_end {
+            let i = self.pattern_index(global_step);
+            let step = self.seq_notes[i];
+            if !step.condition.is_met(seq.is_filling) {
+                global_step += 1;
+                continue;
+            }
+            let own_sound = self.sound();
+            let sound = SeqStep {
+                model: step.model.or(own_sound.model),
+                harmonic: step.harmonic.or(own_sound.harmonic),
+                timbre: step.timbre.or(own_sound.timbre),
+                morph: step.morph.or(own_sound.morph),
+                decay: step.decay.or(own_sound.decay),
+                lpg_colour: step.lpg_colour.or(own_sound.lpg_colour),
+                velocity: step.velocity,
+                ..Default::default()
+            };
+            let tick = self.step_tick(global_step);
+            let gate_off = self.gate_off_tick(global_step, ticks_per_sample);
+            let mut chord = Vec::new();
+            for note in step.chord.into_iter().flatten() {
+                chord.push(ChordNote { tick, gate_off, step: SeqStep { note: Some(note), ..sound } });
             }
+            if !chord.is_empty() { chords.push(chord); }
+            global_step += 1;
+        }
+        chords
+    }
+
+    /// Sound of the voice as a step that locks every parameter of it.
+    fn sound (&self) -> SeqStep {
+        SeqStep {
+            model: Some(self.patch.engine),
+            harmonic: Some(self.target_harmonic),
+            timbre: Some(self.target_timbre),
+            morph: Some(self.target_morph),
+            decay: Some(self.patch.decay),
+            lpg_colour: Some(self.patch.lpg_colour),
+            ..Default::default()
         }
     }
 
+    /// Whether the voice can lend itself to chord notes: it has no pattern of its own and no keys held.
+    pub fn is_idle (&self) -> bool {
+        self.seq_notes.is_empty() && self.pressed_set.is_empty()
+    }
+
+    pub fn play_chord_note (&mut self, chord_note: ChordNote) {
+        let i = self.chord_notes.partition_point(|queued| queued.tick <= chord_note.tick);
+        self.chord_notes.insert(i, chord_note);
+    }
+
+    /// First and last step that play, the whole pattern unless a loop is set within it.
+    pub fn loop_bounds (&self) -> (usize, usize) {
+        let last = self.seq_notes.len().saturating_sub(1);
+        let (start, end) = self.loop_range.unwrap_or((0, last));
+        (start.min(last), end.min(last))
+    }
+
+    /// Index into the pattern of the step played at `global_step`, following the direction and the loop.
+    fn pattern_index (&self, global_step: i64) -> usize {
+        let (start, end) = self.loop_bounds();
+        let len = (end - start + 1) as i64;
+        let offset = match self.direction {
+            Direction::Forward => global_step.rem_euclid(len),
+            Direction::Reverse => len - 1 - global_step.rem_euclid(len),
+            Direction::PingPong => {
+                let turn = global_step.rem_euclid(2 * len);
+                if turn < len { turn } else { 2 * len - 1 - turn }
+            },
+            Direction::Pendulum if len > 1 => {
+                let turn = global_step.rem_euclid(2 * len - 2);
+                if turn < len { turn } else { 2 * len - 2 - turn }
+            },
+            Direction::Pendulum => 0,
+            Direction::Random => ((jitter(self.seed.rotate_left(32), global_step) + 1.) / 2. * len as f32) as i64 % len,
+        };
+        start + offset as usize
+    }
+
+    /// Clock tick a step is triggered at, where `global_step` counts steps from the start of the song.
+    fn step_tick (&self, global_step: i64) -> f64 {
+        let i = self.pattern_index(global_step);
+        let humanize = (self.humanize * HUMANIZE_TIMING * jitter(self.seed, global_step)) as f64;
+        let offset = (self.seq_notes[i].offset + humanize).clamp(-MAX_NUDGE, MAX_NUDGE);
+        ((global_step as f64 + offset) * TICKS_PER_STEP).max(0.)
+    }
+
+    fn is_sequencer_running (&self, seq: &Sequencer) -> bool {
+        seq.is_playing() || seq.is_overdubbing() || seq.is_recording() && self.record_mode == RecordMode::Live
+    }
+
+    fn trigger_step (&mut self, step: &SeqStep) {
+        if let Some(note) = step.note {
+            self.glide_to(note, step.slide, step.glide);
+            // a slide keeps the gate of the previous note open, so the engine is not struck again
+            if !step.slide || self.modulations.trigger == 0. {
+                self.modulations.trigger = 1.;
+                self.set_velocity(step.velocity.unwrap_or(DEFAULT_VELOCITY));
+            }
+        }
+        if let Some(model) = step.model { self.patch.engine = model; }
+        if let Some(harmonic) = step.harmonic { 
+            self.smooth_harmonic = harmonic;
+            self.target_harmonic = harmonic;
+        }
+        if let Some(timbre) = step.timbre { 
+            self.smooth_timbre = timbre;
+            self.target_timbre = timbre;
+        }
+        if let Some(morph) = step.morph { 
+            self.smooth_morph = morph;
+            self.target_morph = morph;
+        }
+        if let Some(decay) = step.decay { 
+            self.patch.decay = decay;
+        }
+        if let Some(volume) = step.volume { self.volume = volume; }
+        if let Some(pan) = step.pan { self.pan = pan; }
+        if let Some(balance) = step.balance { self.balance = balance; }
+        if let Some(lpg_colour) = step.lpg_colour { self.patch.lpg_colour = lpg_colour; }
+        // the control loop cannot run before the block is rendered, so the step has to reach the engine from here
+        if !self.is_gliding { self.smooth_note = self.note; }
+        self.update_patch();
+    }
+
+    fn set_velocity (&mut self, velocity: f32) {
+        self.modulations.level = velocity;
+        self.velocity_timbre = VELOCITY_TIMBRE_DEPTH * (velocity - DEFAULT_VELOCITY);
+    }
+
+    fn glide_to (&mut self, note: f32, slide: bool, glide_time: Option<f32>) {
+        self.note = note;
+        self.is_gliding = slide;
+        self.active_glide_time = glide_time.unwrap_or(self.glide_time);
+    }
+
+    fn release_step (&mut self) {
+        self.modulations.trigger = 0.;
+        self.modulations.level = 0.;
+        // a chord note is over, so the voice gets its own sound back
+        if let Some(own_sound) = self.own_sound.take() { self.trigger_step(&own_sound); }
+    }
+
     pub fn control_loop (arc_synth: Arc<Mutex<Synth>>) {
+        let per_period = |factor: f32| 1. - (1. - factor as f64).powi(SMOOTHING_STEPS_PER_PERIOD) as f32;
+        let bend_factor = per_period(BEND_SMOOTH_FACTOR);
+        let vibrato_press_factor = per_period(VIBRATO_PRESS_SMOOTH_FACTOR);
+        let vibrato_release_factor = per_period(VIBRATO_RELEASE_SMOOTH_FACTOR);
+        let param_factor = per_period(PARAM_SMOOTH_FACTOR);
         loop {
-            println!("debug loop!!!");
-            let _is_playing = TRANSPORT.condvar.wait(
-                TRANSPORT.is_playing.lock().unwrap()
-            ).unwrap().to_owned();
+            thread::sleep(CONTROL_PERIOD);
             let mut s = arc_synth.lock().unwrap();
-            let time  = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_millis() as f32 / 1000.;
 
-            s.smooth_bend = (BEND_SMOOTH_FACTOR * s.target_bend) + (BEND_SMOOTH_FACTOR_INV * s.smooth_bend);
+            // scenes only take over while the crossfade moves, so edits made in between are kept
+            if (s.target_scene_morph - s.smooth_scene_morph).abs() > SCENE_MORPH_THRESHOLD {
+                let morph_factor = 1. - (-3. * CONTROL_PERIOD.as_secs_f32() / SCENE_MORPH_TIME).exp();
+                s.smooth_scene_morph = (morph_factor * s.target_scene_morph) + ((1. - morph_factor) * s.smooth_scene_morph);
+                s.apply_scene_morph();
+            }
+            s.smooth_bend = (bend_factor * s.target_bend) + ((1. - bend_factor) * s.smooth_bend);
             s.smooth_vibrato_amount = f32::min(
-                (VIBRATO_PRESS_SMOOTH_FACTOR * s.target_vibrato_amount) + (VIBRATO_PRESS_SMOOTH_FACTOR_INV * s.smooth_vibrato_amount),
-                (VIBRATO_RELEASE_SMOOTH_FACTOR * s.target_vibrato_amount) + (VIBRATO_RELEASE_SMOOTH_FACTOR_INV * s.smooth_vibrato_amount),
+                (vibrato_press_factor * s.target_vibrato_amount) + ((1. - vibrato_press_factor) * s.smooth_vibrato_amount),
+                (vibrato_release_factor * s.target_vibrato_amount) + ((1. - vibrato_release_factor) * s.smooth_vibrato_amount),
             );
-            let vibrato =  s.smooth_vibrato_amount * (VIBRATO_RATE * time).sin();
-            s.patch.note = s.note + s.transpose + s.smooth_bend + vibrato;
-            
-            s.smooth_harmonic = (PARAM_SMOOTH_FACTOR * s.target_harmonic) + (PARAM_SMOOTH_FACTOR_INV * s.smooth_harmonic);
-            s.smooth_timbre   = (PARAM_SMOOTH_FACTOR * s.target_timbre)   + (PARAM_SMOOTH_FACTOR_INV * s.smooth_timbre);
-            s.smooth_morph    = (PARAM_SMOOTH_FACTOR * s.target_morph)    + (PARAM_SMOOTH_FACTOR_INV * s.smooth_morph);   
-            s.patch.harmonics = s.smooth_harmonic;
-            s.patch.timbre    = s.smooth_timbre;
-            s.patch.morph     = s.smooth_morph;
+            s.smooth_harmonic = (param_factor * s.target_harmonic) + ((1. - param_factor) * s.smooth_harmonic);
+            s.smooth_timbre   = (param_factor * s.target_timbre)   + ((1. - param_factor) * s.smooth_timbre);
+            s.smooth_morph    = (param_factor * s.target_morph)    + ((1. - param_factor) * s.smooth_morph);   
+            // slides are exponential, and cover 95% of the interval within the glide time
+            let glide_factor = if s.is_gliding && s.active_glide_time > 0. {
+                1. - (-3. * CONTROL_PERIOD.as_secs_f32() / s.active_glide_time).exp()
+            } else { 1. };
+            s.smooth_note = (glide_factor * s.note) + ((1. - glide_factor) * s.smooth_note);
+            s.update_patch();
+        }
+    }
+
+    /// Hands the smoothed values over to the engine.
+    fn update_patch (&mut self) {
+        let time  = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_millis() as f32 / 1000.;
+        let vibrato =  self.smooth_vibrato_amount * (VIBRATO_RATE * time).sin();
+        self.patch.note = self.smooth_note + self.transpose + self.smooth_bend + vibrato;
+        
+        self.patch.harmonics = self.smooth_harmonic;
+        self.patch.timbre    = (self.smooth_timbre + self.velocity_timbre).clamp(0., 1.);
+        self.patch.morph     = self.smooth_morph;
+    }
+
+    fn current_scene (&self) -> Scene {
+        Scene {
+            model: self.patch.engine,
+            harmonic: self.target_harmonic,
+            timbre: self.target_timbre,
+            morph: self.target_morph,
+            decay: self.patch.decay,
+            volume: self.volume,
+            pan: self.pan,
         }
     }
 
+    fn apply_scene_morph (&mut self) {
+        let [Some(a), Some(b)] = self.scenes else { return };
+        let scene = Scene::crossfade(&a, &b, self.smooth_scene_morph);
+        self.patch.engine = scene.model;
+        self.target_harmonic = scene.harmonic;
+        self.target_timbre = scene.timbre;
+        self.target_morph = scene.morph;
+        self.patch.decay = scene.decay;
+        self.volume = scene.volume;
+        self.pan = scene.pan;
+    }
+
+    /// Stamps the time the step is heard, which is a block after it is rendered as the output device buffers it.
+    fn move_playhead (&mut self, global_step: i64, block_time: Instant, sec_from_block: f64) {
+        self.playhead = global_step;
+        let sec_from_block = sec_from_block + self.block_size as f64 / SAMPLE_RATE as f64;
+        self.playhead_time = if sec_from_block >= 0. {
+            block_time + Duration::from_secs_f64(sec_from_block)
+        } else {
+            block_time - Duration::from_secs_f64(-sec_from_block)
+        };
+    }
+
+    /// Position of the sequencer in steps since the start of the song, including the fraction of the current step.
+    fn playhead_position (&self) -> f64 {
+        let sec_per_8th = 30. / self.tempo;
+        let elapsed = Instant::now().saturating_duration_since(self.playhead_time).as_secs_f64();
+        self.playhead as f64 + elapsed / sec_per_8th
+    }
+
+    fn is_live_recording (&self) -> bool {
+        self.seq_status == SeqStatus::Overdub ||
+        self.seq_status == SeqStatus::Recording && self.record_mode == RecordMode::Live
+    }
+
+    /// Merges a parameter change into the step the playhead is currently on.
+    fn overdub_step<T: ToPrimitive + NumCast> (&mut self, param: Param, value: T) {
+        let len = self.seq_notes.len();
+        if len == 0 { return; }
+        let step = self.pattern_index(self.playhead_position().floor() as i64);
+        write_param(&mut self.seq_notes[step], &param, value);
+    }
+
+    fn live_note_on (&mut self, key: i32, note: f32) {
+        let len = self.seq_notes.len();
+        if len == 0 { return; }
+        let position = self.playhead_position();
+        let grid = position.round();
+        let quantized = position + (grid - position) * self.quantize;
+        let step = self.pattern_index(grid as i64);
+        let live_step = &mut self.seq_notes[step];
+        live_step.note = Some(note);
+        live_step.chord = Default::default();
+        live_step.slide = self.slide;
+        live_step.gate_length = Some(self.gate_length);
+        live_step.offset = quantized - grid;
+        self.live_note = Some(LiveNote { key, step, onset: quantized });
+        self.last_live_step = Some(step);
+    }
+
+    /// Step the note just played was recorded to, live, in step mode or in the step editor.
+    fn recorded_step (&mut self) -> Option<&mut SeqStep> {
+        let len = self.seq_notes.len();
+        let step = if self.is_live_recording() && !self.is_editing() {
+            self.live_note.map(|live_note| live_note.step)
+        } else if let Some(cursor) = self.cursor {
+            Some((cursor + len.max(1) - 1) % len.max(1))
+        } else if self.seq_status == SeqStatus::Recording {
+            len.checked_sub(1)
+        } else {
+            None
+        };
+        step.and_then(|step| self.seq_notes.get_mut(step))
+    }
+
+    /// Stacks a note on the step that is being held.
+    fn record_chord_note (&mut self, note: f32) {
+        let Some(step) = self.recorded_step() else { return };
+        if step.note.is_some() { step.add_chord_note(note); }
+    }
+
+    fn is_note_recording (&self) -> bool {
+        self.seq_status == SeqStatus::Recording || self.is_live_recording() || self.is_editing()
+    }
+
+    fn is_chord_recording (&self) -> bool {
+        self.seq_status == SeqStatus::Recording && self.record_mode == RecordMode::Step || self.is_editing()
+    }
+
+    fn live_note_off (&mut self, key: i32) {
+        let Some(live_note) = self.live_note.filter(|live_note| live_note.key == key) else { return };
+        let position = self.playhead_position();
+        if let Some(live_step) = self.seq_notes.get_mut(live_note.step) {
+            let held = position - live_note.onset;
+            live_step.gate_length = Some(held.clamp(LIVE_MIN_GATE_LENGTH, 1.));
+        }
+        self.live_note = None;
+    }
+
     fn update_first_step (&mut self, param: &Param) {
         if self.seq_notes.is_empty() {
             self.first_step_backup = SeqStep {
@@ -194,6 +655,12 @@ impl<'a> Synth<'a> {
                 morph: Some(self.target_morph),
                 decay: Some(self.patch.decay),
                 gate_length: Some(self.gate_length),
+                glide: Some(self.glide_time),
+                volume: Some(self.volume),
+                pan: Some(self.pan),
+                balance: Some(self.balance),
+                lpg_colour: Some(self.patch.lpg_colour),
+                velocity: Some(self.velocity),
                 ..Default::default()
             }
         }
@@ -206,6 +673,12 @@ impl<'a> Synth<'a> {
                 Param::Morph => recover_param(&mut first_step.morph, self.first_step_backup.morph),
                 Param::Decay => recover_param(&mut first_step.decay, self.first_step_backup.decay),
                 Param::GateLength => recover_param(&mut first_step.gate_length, self.first_step_backup.gate_length),
+                Param::Glide => recover_param(&mut first_step.glide, self.first_step_backup.glide),
+                Param::Volume => recover_param(&mut first_step.volume, self.first_step_backup.volume),
+                Param::Pan => recover_param(&mut first_step.pan, self.first_step_backup.pan),
+                Param::Balance => recover_param(&mut first_step.balance, self.first_step_backup.balance),
+                Param::LpgColour => recover_param(&mut first_step.lpg_colour, self.first_step_backup.lpg_colour),
+                Param::Velocity => recover_param(&mut first_step.velocity, self.first_step_backup.velocity),
                 _ => (),
             };
         }
@@ -215,56 +688,130 @@ impl<'a> Synth<'a> {
             self.seq_notes.push(SeqStep::default());
         }
         let last_step = self.seq_notes.last_mut().unwrap();
-        let value = NumCast::from(value).unwrap();
-        match param {
-            Param::Rest => last_step.note = None,
-            Param::Note => last_step.note = Some(value),
-            Param::Model => last_step.model = Some(NumCast::from(value).unwrap()),
-            Param::Harmonic => last_step.harmonic = Some(value),
-            Param::Timbre => last_step.timbre = Some(value),
-            Param::Morph => last_step.morph = Some(value),
-            Param::Decay => last_step.decay = Some(value),
-            Param::GateLength => last_step.gate_length = Some(NumCast::from(value).unwrap()),
-        };
+        write_param(last_step, &param, value);
+        if let Param::Note = param { last_step.slide = self.slide; }
         match param {
             Param::Rest | Param::Note => last_step.is_awaiting_note = false,
             _ => last_step.is_awaiting_note = true,
         }
     }
 
+    /// Writes into the step under the cursor, starting from the value it already locks. Notes and rests move the cursor on.
+    fn edit_step<T> (&mut self, param: Param, value: &mut T, update: impl FnOnce(&mut T))
+    where T: Num + ToPrimitive + NumCast + Copy {
+        let Some(cursor) = self.cursor else { return };
+        if self.seq_notes.is_empty() {
+            self.seq_notes.push(SeqStep::default());
+        }
+        let Some(step) = self.seq_notes.get_mut(cursor) else { return };
+        if let Some(locked) = read_param(step, &param) {
+            *value = NumCast::from(locked).unwrap();
+        }
+        update(value);
+        write_param(step, &param, *value);
+        if let Param::Note | Param::Rest = param {
+            step.is_awaiting_note = false;
+            self.cursor_next();
+        }
+    }
+
     fn recording_wrapper<T> (&mut self, param: Param, value: &mut T, update: impl FnOnce(&mut T) -> ()) 
     where T: Num + ToPrimitive + NumCast + Copy {
-        let is_recording = self.seq_status == SeqStatus::Recording;
+        if self.is_editing() { return self.edit_step(param, value, update); }
+        let is_live_recording = self.is_live_recording();
+        let is_recording = self.seq_status == SeqStatus::Recording && !is_live_recording;
         if is_recording { self.update_first_step(&param);}
         update(value);
         if is_recording { self.record_step(param, *value);}
+        else if is_live_recording { self.overdub_step(param, *value); }
     }
 
     
     pub fn render(&mut self, out: &mut [f32], aux: &mut [f32]) {
-        self.synth_engine.render(&self.patch, &self.modulations, out, aux);
+        let frame = self.render_gate_gap(out, aux);
+        self.synth_engine.render(&self.patch, &self.modulations, &mut out[frame..], &mut aux[frame..]);
     }
 
-    pub fn note_on(&mut self, key: i32) {
-        let note_ptr: &mut f32 = unsafe { transmute(&mut self.note) };
+    /// Closes the gate for the first sample when a key retriggers over a held one, so the engine sees a new rising edge.
+    /// Returns the number of samples rendered.
+    fn render_gate_gap (&mut self, out: &mut [f32], aux: &mut [f32]) -> usize {
+        if !self.is_retriggering || out.is_empty() { return 0; }
+        self.is_retriggering = false;
+        let trigger = self.modulations.trigger;
+        self.modulations.trigger = 0.;
+        self.synth_engine.render(&self.patch, &self.modulations, &mut out[..1], &mut aux[..1]);
+        self.modulations.trigger = trigger;
+        1
+    }
+
+    fn priority_key (&self) -> Option<i32> {
+        self.note_priority.pick(self.pressed_set.iter().copied())
+    }
+
+    /// Moves from the key that sounded to `key`, gliding if legato and striking the engine again otherwise.
+    fn change_held_note (&mut self, note: f32, was_held: bool) {
+        let is_legato = was_held && self.legato;
+        self.glide_to(note, self.slide || is_legato, None);
+        self.is_retriggering = was_held && !is_legato;
+    }
+
+    /// Plays and records a note, louder and brighter if `accent`.
+    pub fn note_on(&mut self, key: i32, accent: bool) {
         let note = key2note(key) + self.rec_transpose;
-        self.recording_wrapper(Param::Note, note_ptr, |n: &mut f32| *n = note);
-        self.modulations.trigger = 1.0;
-        self.modulations.level = 1.0;
+        let velocity = if accent { ACCENT_VELOCITY } else { self.velocity };
+        // further keys pressed while a note is held join it in the same step,
+        // except when recording live, where an overlapping note is the next one of a legato phrase
+        let is_chord = self.pressed_set.iter().any(|&pressed| pressed != key);
+        if is_chord && self.is_chord_recording() {
+            self.record_chord_note(note);
+            self.pressed_set.insert(key);
+            return;
+        }
+        let sounding = self.priority_key();
         self.pressed_set.insert(key);
+        // a held key with a higher priority keeps sounding
+        if self.priority_key() != Some(key) { return; }
+        if self.is_live_recording() && !self.is_editing() {
+            self.live_note_on(key, note);
+        }
+        else {
+            let note_ptr: &mut f32 = unsafe { transmute(&mut self.note) };
+            self.recording_wrapper(Param::Note, note_ptr, |n: &mut f32| *n = note);
+        }
+        if self.is_note_recording() {
+            if let Some(step) = self.recorded_step() { step.velocity = (velocity != DEFAULT_VELOCITY).then_some(velocity); }
+        }
+        if sounding != Some(key) { self.change_held_note(note, sounding.is_some()); }
+        self.modulations.trigger = 1.0;
+        self.set_velocity(velocity);
     }
 
+    /// Lets go of `key`, going back to the held key with the highest priority if `key` was sounding.
     pub fn note_off(&mut self, key: i32) {    
+        let sounding = self.priority_key();
         self.pressed_set.remove(&key);
-        if self.pressed_set.is_empty() {
-            self.modulations.trigger = 0.0;
-            self.modulations.level = 0.0;
-        } 
-        else {
-            self.note = key2note(*self.pressed_set.back().unwrap());
+        if self.is_live_recording() && !self.is_editing() { self.live_note_off(key); }
+        match self.priority_key() {
+            None => {
+                self.modulations.trigger = 0.0;
+                self.modulations.level = 0.0;
+            },
+            Some(held) if Some(held) != sounding => self.change_held_note(key2note(held) + self.rec_transpose, true),
+            Some(_) => (),
         }
     }
 
+    /// Lets go of every key held on this voice, as if they had all been released.
+    pub fn release_keys (&mut self) {
+        self.pressed_set.clear();
+        self.is_retriggering = false;
+        self.live_note = None;
+        self.modulations.trigger = 0.0;
+        self.modulations.level = 0.0;
+        self.pitch_bend_neutral();
+        self.vibrato_off();
+    }
+
     pub fn add_rest (&mut self) {
         self.rec_transpose = 0.;
         let note_ptr: &mut f32 = unsafe { transmute(&mut self.note) };
@@ -331,6 +878,78 @@ impl<'a> Synth<'a> {
         self.recording_wrapper (Param::GateLength, gate_length_ptr, dec_f64);
     }
 
+    pub fn volume_up (&mut self) {
+        let volume_ptr: &mut f32 = unsafe { transmute(&mut self.volume) };
+        self.recording_wrapper (Param::Volume, volume_ptr, inc_f32);
+    }
+
+    pub fn volume_down (&mut self) {
+        let volume_ptr: &mut f32 = unsafe { transmute(&mut self.volume) };
+        self.recording_wrapper (Param::Volume, volume_ptr, dec_f32);
+    }
+
+    pub fn pan_right (&mut self) {
+        let pan_ptr: &mut f32 = unsafe { transmute(&mut self.pan) };
+        self.recording_wrapper (Param::Pan, pan_ptr, inc_f32);
+    }
+
+    pub fn pan_left (&mut self) {
+        let pan_ptr: &mut f32 = unsafe { transmute(&mut self.pan) };
+        self.recording_wrapper (Param::Pan, pan_ptr, dec_f32);
+    }
+
+    pub fn balance_up (&mut self) {
+        let balance_ptr: &mut f32 = unsafe { transmute(&mut self.balance) };
+        self.recording_wrapper (Param::Balance, balance_ptr, inc_f32);
+    }
+
+    pub fn balance_down (&mut self) {
+        let balance_ptr: &mut f32 = unsafe { transmute(&mut self.balance) };
+        self.recording_wrapper (Param::Balance, balance_ptr, dec_f32);
+    }
+
+    pub fn lpg_colour_up (&mut self) {
+        let lpg_colour_ptr: &mut f32 = unsafe { transmute(&mut self.patch.lpg_colour) };
+        self.recording_wrapper (Param::LpgColour, lpg_colour_ptr, inc_f32);
+    }
+
+    pub fn lpg_colour_down (&mut self) {
+        let lpg_colour_ptr: &mut f32 = unsafe { transmute(&mut self.patch.lpg_colour) };
+        self.recording_wrapper (Param::LpgColour, lpg_colour_ptr, dec_f32);
+    }
+
+    pub fn velocity_up (&mut self) {
+        let velocity_ptr: &mut f32 = unsafe { transmute(&mut self.velocity) };
+        self.recording_wrapper (Param::Velocity, velocity_ptr, inc_f32);
+    }
+
+    pub fn velocity_down (&mut self) {
+        let velocity_ptr: &mut f32 = unsafe { transmute(&mut self.velocity) };
+        self.recording_wrapper (Param::Velocity, velocity_ptr, dec_f32);
+    }
+
+    pub fn glide_up (&mut self) {
+        let glide_ptr: &mut f32 = unsafe { transmute(&mut self.glide_time) };
+        self.recording_wrapper (Param::Glide, glide_ptr, inc_f32);
+    }
+
+    pub fn glide_down (&mut self) {
+        let glide_ptr: &mut f32 = unsafe { transmute(&mut self.glide_time) };
+        self.recording_wrapper (Param::Glide, glide_ptr, dec_f32);
+    }
+
+    /// Toggles the slide of the step under the cursor, or else whether the next notes slide.
+    pub fn toggle_slide (&mut self) {
+        match self.cursor.and_then(|cursor| self.seq_notes.get_mut(cursor)) {
+            Some(step) => step.slide = !step.slide,
+            None => self.slide = !self.slide,
+        }
+    }
+
+    pub fn toggle_legato (&mut self) { self.legato = !self.legato; }
+
+    pub fn next_note_priority (&mut self) { self.note_priority = self.note_priority.next(); }
+
     pub fn transpose_up (&mut self) { 
         self.transpose     += 12.;
         self.rec_transpose += 12.;
@@ -346,14 +965,300 @@ impl<'a> Synth<'a> {
     pub fn start_recording_or_undo_last (&mut self, seq: &mut MutexGuard<Sequencer>) {
         self.rec_transpose = 0.;
         if seq.is_recording() {
-            self.seq_notes.pop();
+            match self.record_mode {
+                RecordMode::Step => { 
+                    self.seq_notes.pop();
+                    self.clamp_cursor();
+                },
+                RecordMode::Live => if let Some(step) = self.last_live_step.take() {
+                    if let Some(live_step) = self.seq_notes.get_mut(step) {
+                        live_step.note = None;
+                        live_step.chord = Default::default();
+                        live_step.gate_length = None;
+                        live_step.offset = 0.;
+                    }
+                },
+            }
         }
         else {
-            seq.start_recording();
+            seq.start_recording(self.record_mode == RecordMode::Live);
+            if self.record_mode == RecordMode::Live && self.seq_notes.is_empty() {
+                self.seq_notes = vec![SeqStep::default(); LIVE_RECORD_STEPS];
+            }
         }
+        self.seq_status = seq.status;
+    }
+
+    pub fn toggle_record_mode (&mut self) {
+        self.live_note = None;
+        self.record_mode = match self.record_mode {
+            RecordMode::Step => RecordMode::Live,
+            RecordMode::Live => RecordMode::Step,
+        };
     }
+
+    pub fn quantize_up   (&mut self) { inc_f64(&mut self.quantize); }
+    pub fn quantize_down (&mut self) { dec_f64(&mut self.quantize); }
+
     pub fn clear_notes (&mut self) {
         self.seq_notes = Vec::default();
+        if self.is_editing() { self.cursor = Some(0); }
+    }
+
+    pub fn is_editing (&self) -> bool { self.cursor.is_some() }
+
+    pub fn toggle_step_editor (&mut self) {
+        self.cursor = match self.cursor {
+            Some(_) => None,
+            None => Some(0),
+        };
+    }
+
+    /// Keeps the cursor on the pattern after its length has changed.
+    fn clamp_cursor (&mut self) {
+        if let Some(cursor) = self.cursor {
+            self.cursor = Some(cursor.min(self.seq_notes.len().saturating_sub(1)));
+        }
+    }
+
+    pub fn cursor_next (&mut self) {
+        let len = self.seq_notes.len().max(1);
+        self.cursor = self.cursor.map(|cursor| (cursor + 1) % len);
+    }
+
+    pub fn cursor_prev (&mut self) {
+        let len = self.seq_notes.len().max(1);
+        self.cursor = self.cursor.map(|cursor| (cursor + len - 1) % len);
+    }
+
+    pub fn insert_step (&mut self) {
+        let Some(cursor) = self.cursor else { return };
+        self.seq_notes.insert(cursor.min(self.seq_notes.len()), SeqStep::default());
+    }
+
+    pub fn delete_step (&mut self) {
+        let Some(cursor) = self.cursor else { return };
+        if cursor < self.seq_notes.len() {
+            self.seq_notes.remove(cursor);
+        }
+        self.clamp_cursor();
+    }
+
+    pub fn duplicate_step (&mut self) {
+        let Some(cursor) = self.cursor else { return };
+        let Some(&step) = self.seq_notes.get(cursor) else { return };
+        self.seq_notes.insert(cursor + 1, step);
+        self.cursor = Some(cursor + 1);
+    }
+
+    /// Degree the pattern is inverted around: the note under the cursor, or else the first note of the pattern.
+    fn inversion_axis (&self) -> i32 {
+        let cursor_note = self.cursor.and_then(|cursor| self.seq_notes.get(cursor)?.note);
+        let first_note = self.seq_notes.iter().find_map(|step| step.note);
+        cursor_note.or(first_note).map_or(0, note2degree)
+    }
+
+    fn transform_wrapper (&mut self, transform: impl FnOnce(&mut Vec<SeqStep>)) {
+        transform(&mut self.seq_notes);
+        self.clamp_cursor();
+    }
+
+    pub fn reverse_pattern (&mut self) { self.transform_wrapper(|steps| transform::reverse(steps)); }
+    pub fn rotate_pattern_left  (&mut self) { self.transform_wrapper(|steps| transform::rotate(steps, -1)); }
+    pub fn rotate_pattern_right (&mut self) { self.transform_wrapper(|steps| transform::rotate(steps, 1)); }
+    pub fn stretch_pattern (&mut self) { self.transform_wrapper(transform::stretch); }
+    pub fn shrink_pattern  (&mut self) { self.transform_wrapper(transform::shrink); }
+
+    pub fn invert_pattern (&mut self) {
+        let axis = self.inversion_axis();
+        self.transform_wrapper(|steps| transform::invert(steps, axis));
+    }
+
+    pub fn retrograde_invert_pattern (&mut self) {
+        let axis = self.inversion_axis();
+        self.transform_wrapper(|steps| transform::retrograde_inversion(steps, axis));
+    }
+
+    pub fn shuffle_pattern (&mut self) {
+        let mut rng = self.rng.clone();
+        self.transform_wrapper(|steps| transform::shuffle(steps, &mut rng));
+        self.rng = rng;
+    }
+
+    /// Replaces the pattern with a melody generated by a Markov chain trained on `patterns`.
+    pub fn generate_pattern (&mut self, patterns: &[Vec<SeqStep>]) {
+        let length = self.generator_length;
+        let generated = Markov::train(patterns).generate(length, &mut self.rng);
+        if generated.is_empty() { return; }
+        self.transform_wrapper(|steps| *steps = generated);
+    }
+
+    pub fn generator_length_up   (&mut self) { self.generator_length = (self.generator_length + 1).min(MAX_GENERATOR_LENGTH); }
+    pub fn generator_length_down (&mut self) { self.generator_length = (self.generator_length - 1).max(MIN_GENERATOR_LENGTH); }
+
+    pub fn is_turing_running (&self) -> bool { self.turing_backup.is_some() }
+
+    /// Swaps the pattern for a Turing machine register, or brings the pattern back when it is stopped.
+    pub fn toggle_turing (&mut self) {
+        match self.turing_backup.take() {
+            Some(backup) => self.seq_notes = backup,
+            None => {
+                let mut register = Vec::new();
+                self.turing.fill(&mut register, &mut self.rng);
+                self.turing_backup = Some(std::mem::replace(&mut self.seq_notes, register));
+            },
+        }
+        self.clamp_cursor();
+    }
+
+    /// Stops the Turing machine and keeps its current loop as the pattern.
+    pub fn freeze_turing (&mut self) { self.turing_backup = None; }
+
+    pub fn turing_lock_up   (&mut self) { inc_f32(&mut self.turing.lock); }
+    pub fn turing_lock_down (&mut self) { dec_f32(&mut self.turing.lock); }
+
+    pub fn turing_length_up (&mut self) {
+        self.turing.length_up();
+        self.fill_turing();
+    }
+
+    pub fn turing_length_down (&mut self) {
+        self.turing.length_down();
+        self.fill_turing();
+    }
+
+    fn fill_turing (&mut self) {
+        if !self.is_turing_running() { return; }
+        self.turing.fill(&mut self.seq_notes, &mut self.rng);
+        self.clamp_cursor();
+    }
+
+    /// Stores the sound of the voice as scene A (0) or B (1).
+    pub fn store_scene (&mut self, index: usize) {
+        self.scenes[index] = Some(self.current_scene());
+    }
+
+    pub fn has_scene (&self, index: usize) -> bool { self.scenes[index].is_some() }
+
+    pub fn scene_morph_to_a (&mut self) { dec_f32(&mut self.target_scene_morph); }
+    pub fn scene_morph_to_b (&mut self) { inc_f32(&mut self.target_scene_morph); }
+
+    pub fn snapshot (&self) -> Snapshot {
+        Snapshot {
+            seq_notes: self.seq_notes.clone(),
+            turing_backup: self.turing_backup.clone(),
+            model: self.patch.engine,
+            harmonic: self.target_harmonic,
+            timbre: self.target_timbre,
+            morph: self.target_morph,
+            decay: self.patch.decay,
+            lpg_colour: self.patch.lpg_colour,
+            volume: self.volume,
+            pan: self.pan,
+            balance: self.balance,
+            velocity: self.velocity,
+            gate_length: self.gate_length,
+            glide_time: self.glide_time,
+            slide: self.slide,
+        }
+    }
+
+    fn restore (&mut self, snapshot: Snapshot) {
+        self.seq_notes = snapshot.seq_notes;
+        self.turing_backup = snapshot.turing_backup;
+        self.patch.engine = snapshot.model;
+        self.target_harmonic = snapshot.harmonic;
+        self.target_timbre = snapshot.timbre;
+        self.target_morph = snapshot.morph;
+        self.patch.decay = snapshot.decay;
+        self.patch.lpg_colour = snapshot.lpg_colour;
+        self.volume = snapshot.volume;
+        self.pan = snapshot.pan;
+        self.balance = snapshot.balance;
+        self.velocity = snapshot.velocity;
+        self.gate_length = snapshot.gate_length;
+        self.glide_time = snapshot.glide_time;
+        self.slide = snapshot.slide;
+        self.clamp_cursor();
+    }
+
+    /// Adds the command that just ran to the undo history, if it changed the state it was given before.
+    pub fn commit_history (&mut self, before: Snapshot) {
+        let after = self.snapshot();
+        self.history.commit(before, &after);
+    }
+
+    /// Takes back the last command, on this voice or on the tempo that all voices share, whichever ran last.
+    pub fn undo (&mut self, seq: &mut Sequencer) {
+        if seq.undo_command() > self.history.undo_command() { return seq.undo(); }
+        let current = self.snapshot();
+        if let Some(previous) = self.history.undo(current) { self.restore(previous); }
+    }
+
+    /// Brings back the command undone last, on this voice or on the tempo.
+    pub fn redo (&mut self, seq: &mut Sequencer) {
+        let is_tempo = match (seq.redo_command(), self.history.redo_command()) {
+            (Some(tempo), Some(voice)) => tempo < voice,
+            (tempo, _) => tempo.is_some(),
+        };
+        if is_tempo { return seq.redo(); }
+        let current = self.snapshot();
+        if let Some(next) = self.history.redo(current) { self.restore(next); }
+    }
+
+    pub fn can_undo (&self, seq: &Sequencer) -> bool { self.history.can_undo() || seq.undo_command().is_some() }
+    pub fn can_redo (&self, seq: &Sequencer) -> bool { self.history.can_redo() || seq.redo_command().is_some() }
+
+    /// Moves the step under the cursor off the grid, by a fraction of a step.
+    fn nudge_step (&mut self, amount: f64) {
+        let Some(step) = self.cursor.and_then(|cursor| self.seq_notes.get_mut(cursor)) else { return };
+        step.offset = ((step.offset + amount) / NUDGE_STEP).round() * NUDGE_STEP;
+        step.offset = step.offset.clamp(-MAX_NUDGE, MAX_NUDGE);
+    }
+
+    pub fn nudge_step_earlier (&mut self) { self.nudge_step(-NUDGE_STEP); }
+    pub fn nudge_step_later   (&mut self) { self.nudge_step(NUDGE_STEP); }
+
+    pub fn next_direction (&mut self) { self.direction = self.direction.next(); }
+
+    pub fn set_loop_start (&mut self) {
+        let Some(cursor) = self.cursor else { return };
+        let (_, end) = self.loop_bounds();
+        self.loop_range = Some((cursor, end.max(cursor)));
+    }
+
+    pub fn set_loop_end (&mut self) {
+        let Some(cursor) = self.cursor else { return };
+        let (start, _) = self.loop_bounds();
+        self.loop_range = Some((start.min(cursor), cursor));
+    }
+
+    pub fn clear_loop (&mut self) { self.loop_range = None; }
+
+    pub fn has_loop (&self) -> bool { self.loop_range.is_some() }
+
+    pub fn cycle_step_condition (&mut self) {
+        let Some(step) = self.cursor.and_then(|cursor| self.seq_notes.get_mut(cursor)) else { return };
+        step.condition = step.condition.next();
+    }
+
+    pub fn toggle_mute (&mut self) { self.is_muted = !self.is_muted; }
+    pub fn toggle_solo (&mut self) { self.is_soloed = !self.is_soloed; }
+
+    /// Whether the voice is heard, given whether any voice is soloed.
+    pub fn is_audible (&self, is_soloing: bool) -> bool {
+        !self.is_muted && (self.is_soloed || !is_soloing)
+    }
+
+    pub fn clear_step_locks (&mut self) {
+        let Some(step) = self.cursor.and_then(|cursor| self.seq_notes.get_mut(cursor)) else { return };
+        *step = SeqStep {
+            note: step.note,
+            chord: step.chord,
+            condition: step.condition,
+            offset: step.offset,
+            ..Default::default()
+        };
     }
 
 
@@ -394,6 +1299,45 @@ impl<'a> AudioGenerator for Synth<'a> {
     }
 }
 
+fn write_param<T: ToPrimitive + NumCast> (step: &mut SeqStep, param: &Param, value: T) {
+    let value = NumCast::from(value).unwrap();
+    match param {
+        Param::Rest => step.note = None,
+        Param::Note => step.note = Some(value),
+        Param::Model => step.model = Some(NumCast::from(value).unwrap()),
+        Param::Harmonic => step.harmonic = Some(value),
+        Param::Timbre => step.timbre = Some(value),
+        Param::Morph => step.morph = Some(value),
+        Param::Decay => step.decay = Some(value),
+        Param::GateLength => step.gate_length = Some(NumCast::from(value).unwrap()),
+        Param::Glide => step.glide = Some(value),
+        Param::Volume => step.volume = Some(value),
+        Param::Pan => step.pan = Some(value),
+        Param::Balance => step.balance = Some(value),
+        Param::LpgColour => step.lpg_colour = Some(value),
+        Param::Velocity => step.velocity = Some(value),
+    };
+}
+
+fn read_param (step: &SeqStep, param: &Param) -> Option<f64> {
+    match param {
+        Param::Rest => None,
+        Param::Note => step.note.map(|value| value as f64),
+        Param::Model => step.model.map(|model| model as f64),
+        Param::Harmonic => step.harmonic.map(|value| value as f64),
+        Param::Timbre => step.timbre.map(|value| value as f64),
+        Param::Morph => step.morph.map(|value| value as f64),
+        Param::Decay => step.decay.map(|value| value as f64),
+        Param::GateLength => step.gate_length,
+        Param::Glide => step.glide.map(|value| value as f64),
+        Param::Volume => step.volume.map(|value| value as f64),
+        Param::Pan => step.pan.map(|value| value as f64),
+        Param::Balance => step.balance.map(|value| value as f64),
+        Param::LpgColour => step.lpg_colour.map(|value| value as f64),
+        Param::Velocity => step.velocity.map(|value| value as f64),
+    }
+}
+
 fn recover_param<T> (parameter: &mut Option<T>, back_up: Option<T>) {
     if parameter.is_none() {
         *parameter = back_up;
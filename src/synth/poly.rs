@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::synth::scale::EDO;
+
+/// How notes played on the keyboard are spread over the voices. Mono plays the selected voice only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolyMode {
+    Mono, RoundRobin, OldestSteal, SameNote
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Slot {
+    key: Option<i32>, // held on the voice
+    last_key: Option<i32>,
+    started: u64,
+}
+
+#[derive(Debug)]
+pub struct VoiceAllocator {
+    pub mode: PolyMode,
+    slots: Vec<Slot>,
+    next: usize,
+    counter: u64,
+}
+
+impl VoiceAllocator {
+    pub fn new () -> Self {
+        Self { mode: PolyMode::Mono, slots: Vec::new(), next: 0, counter: 0 }
+    }
+
+    pub fn is_poly (&self) -> bool { self.mode != PolyMode::Mono }
+
+    pub fn next_mode (&mut self) {
+        self.mode = match self.mode {
+            PolyMode::Mono => PolyMode::RoundRobin,
+            PolyMode::RoundRobin => PolyMode::OldestSteal,
+            PolyMode::OldestSteal => PolyMode::SameNote,
+            PolyMode::SameNote => PolyMode::Mono,
+        };
+        self.slots.clear();
+    }
+
+    /// Voice to play `key` on, and the key that voice has to let go of first when it is stolen.
+    /// A key that is already held, as when the keyboard repeats it, stays on its voice.
+    pub fn note_on (&mut self, key: i32, voice_count: usize) -> (usize, Option<i32>) {
+        self.slots.resize(voice_count, Slot::default());
+        if let Some(voice) = self.slots.iter().position(|slot| slot.key == Some(key)) {
+            return (voice, None);
+        }
+        let same_note = self.slots.iter().position(|slot| slot.last_key == Some(key));
+        let free = (0..voice_count).map(|i| (self.next + i) % voice_count).find(|&i| self.slots[i].key.is_none());
+        let oldest = (0..voice_count).min_by_key(|&i| self.slots[i].started).unwrap_or(0);
+        let voice = match self.mode {
+            PolyMode::Mono => self.next % voice_count,
+            PolyMode::RoundRobin => free.unwrap_or(self.next % voice_count),
+            PolyMode::OldestSteal => free.unwrap_or(oldest),
+            PolyMode::SameNote => same_note.or(free).unwrap_or(oldest),
+        };
+        self.next = (voice + 1) % voice_count;
+        self.counter += 1;
+        let stolen = self.slots[voice].key;
+        self.slots[voice] = Slot { key: Some(key), last_key: Some(key), started: self.counter };
+        (voice, stolen)
+    }
+
+    /// Voice `key` was played on, unless it has been stolen since.
+    pub fn note_off (&mut self, key: i32) -> Option<usize> {
+        let voice = self.slots.iter().position(|slot| slot.key == Some(key))?;
+        self.slots[voice].key = None;
+        Some(voice)
+    }
+}
+
+/// Which of the keys held on a mono voice it plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotePriority {
+    Last, Low, High
+}
+
+impl NotePriority {
+    pub fn next (self) -> Self {
+        match self {
+            Self::Last => Self::Low,
+            Self::Low => Self::High,
+            Self::High => Self::Last,
+        }
+    }
+
+    /// Key that sounds out of the ones held, pressed earliest first.
+    pub fn pick (self, held: impl Iterator<Item = i32>) -> Option<i32> {
+        match self {
+            Self::Last => held.last(),
+            Self::Low => held.min(),
+            Self::High => held.max(),
+        }
+    }
+}
+
+/// How the keyboard is shared between the selected voice and a second one, the zone voice, when not playing polyphonically.
+/// Split gives the bottom two rows to the zone voice, Layer plays every key on both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneMode {
+    Off, Split, Layer
+}
+
+const SPLIT_KEY: i32 = 22; // lowest key of the top two rows
+const MAX_ZONE_INTERVAL: i32 = 2 * EDO;
+
+#[derive(Debug)]
+pub struct KeyZones {
+    pub mode: ZoneMode,
+    pub voice: usize,
+    pub interval: i32, // in steps of 22-EDO, added to the keys the zone voice plays
+    held: HashMap<i32, Vec<(usize, i32)>>,
+}
+
+impl KeyZones {
+    pub fn new () -> Self {
+        Self { mode: ZoneMode::Off, voice: 1, interval: 0, held: HashMap::new() }
+    }
+
+    pub fn is_on (&self) -> bool { self.mode != ZoneMode::Off }
+
+    pub fn next_mode (&mut self) {
+        self.mode = match self.mode {
+            ZoneMode::Off => ZoneMode::Split,
+            ZoneMode::Split => ZoneMode::Layer,
+            ZoneMode::Layer => ZoneMode::Off,
+        };
+        self.held.clear();
+    }
+
+    pub fn next_voice (&mut self, voice_count: usize) { self.voice = (self.voice + 1) % voice_count; }
+
+    pub fn interval_up   (&mut self) { self.interval = (self.interval + 1).min(MAX_ZONE_INTERVAL); }
+    pub fn interval_down (&mut self) { self.interval = (self.interval - 1).max(-MAX_ZONE_INTERVAL); }
+
+    /// Voices to play `key` on, each with the key it plays there. They are kept until the key is released,
+    /// so that it lets go of the same voices even if the zones have been changed in between.
+    pub fn note_on (&mut self, key: i32, selected_voice: usize, voice_count: usize) -> Vec<(usize, i32)> {
+        if let Some(notes) = self.held.get(&key) { return notes.clone(); }
+        let zone_voice = self.voice % voice_count;
+        let zone_note = (zone_voice, key + self.interval);
+        let notes = match self.mode {
+            _ if zone_voice == selected_voice => vec![(selected_voice, key)],
+            ZoneMode::Off => vec![(selected_voice, key)],
+            ZoneMode::Split if key < SPLIT_KEY => vec![zone_note],
+            ZoneMode::Split => vec![(selected_voice, key)],
+            ZoneMode::Layer => vec![(selected_voice, key), zone_note],
+        };
+        self.held.insert(key, notes.clone());
+        notes
+    }
+
+    /// Voices `key` was played on, each with the key it played there.
+    pub fn note_off (&mut self, key: i32) -> Vec<(usize, i32)> {
+        self.held.remove(&key).unwrap_or_default()
+    }
+}
@@ -0,0 +1,20 @@
+// Porcupine[15] as a subset of 22-EDO: steps of 1 and 2 alternating, 15 notes per octave.
+pub const EDO: i32 = 22;
+pub const PORCUPINE_15: [i32; 15] = [0, 1, 3, 4, 6, 7, 9, 10, 12, 13, 15, 16, 18, 19, 21];
+
+/// Nearest scale degree of a note in semitones, counted from the root with 15 degrees per octave.
+pub fn note2degree (note: f32) -> i32 {
+    let edo_step = (note * EDO as f32 / 12.).round() as i32;
+    let octave = edo_step.div_euclid(EDO);
+    let step_in_octave = edo_step.rem_euclid(EDO);
+    let (index, _) = PORCUPINE_15.iter().enumerate()
+        .min_by_key(|(_, scale_step)| (*scale_step - step_in_octave).abs())
+        .unwrap();
+    octave * PORCUPINE_15.len() as i32 + index as i32
+}
+
+pub fn degree2note (degree: i32) -> f32 {
+    let len = PORCUPINE_15.len() as i32;
+    let edo_step = degree.div_euclid(len) * EDO + PORCUPINE_15[degree.rem_euclid(len) as usize];
+    12. * edo_step as f32 / EDO as f32
+}
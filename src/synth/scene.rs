@@ -0,0 +1,27 @@
+/// Snapshot of the sound of a voice, to be recalled or crossfaded against another one.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Scene {
+    pub model: usize,
+    pub harmonic: f32,
+    pub timbre: f32,
+    pub morph: f32,
+    pub decay: f32,
+    pub volume: f32,
+    pub pan: f32,
+}
+
+impl Scene {
+    /// Blends two scenes, `amount` going from `a` to `b`. Models cannot be blended, so they switch halfway.
+    pub fn crossfade (a: &Scene, b: &Scene, amount: f32) -> Scene {
+        let lerp = |from: f32, to: f32| from + (to - from) * amount;
+        Scene {
+            model: if amount < 0.5 { a.model } else { b.model },
+            harmonic: lerp(a.harmonic, b.harmonic),
+            timbre: lerp(a.timbre, b.timbre),
+            morph: lerp(a.morph, b.morph),
+            decay: lerp(a.decay, b.decay),
+            volume: lerp(a.volume, b.volume),
+            pan: lerp(a.pan, b.pan),
+        }
+    }
+}
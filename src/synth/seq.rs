@@ -1,52 +1,415 @@
 use std::sync::{Condvar, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::synth::history::History;
+use crate::synth::metronome::Metronome;
+use crate::synth::poly::{KeyZones, VoiceAllocator};
+use crate::synth::util::{dec_f32, inc_f32};
+use crate::SAMPLE_RATE;
+
+pub const PPQN: u32 = 96;
+pub const TICKS_PER_STEP: f64 = PPQN as f64 / 2.; // steps are 8th notes
+const BEATS_PER_BAR: u32 = 4;
+const MIN_BEATS_PER_BAR: u32 = 2;
+const MAX_BEATS_PER_BAR: u32 = 7;
+const MAX_COUNT_IN_BARS: u32 = 2;
+pub const CHORD_NOTES: usize = 3; // on top of the note of the step
+
+const MIN_TEMPO: f32 = 20.;
+const MAX_TEMPO: f32 = 300.;
+const MAX_TEMPO_RAMP_BARS: u32 = 16;
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+const TAP_COUNT: usize = 4;
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SeqStatus {
-    Recording, Play, Stop
+    CountIn, Recording, Overdub, Play, Pause, Stop
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    Step, Live
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+/// Order the steps of a pattern are played in. Ping-pong plays the end steps twice when turning, pendulum once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward, Reverse, PingPong, Pendulum, Random
+}
+
+impl Direction {
+    pub fn next (&self) -> Self {
+        match self {
+            Direction::Forward => Direction::Reverse,
+            Direction::Reverse => Direction::PingPong,
+            Direction::PingPong => Direction::Pendulum,
+            Direction::Pendulum => Direction::Random,
+            Direction::Random => Direction::Forward,
+        }
+    }
+}
+
+/// When a step plays: always, only while fill is held, or only while it is not.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    #[default] Always, Fill, NotFill
+}
+
+impl Condition {
+    pub fn is_met (&self, is_filling: bool) -> bool {
+        match self {
+            Condition::Always => true,
+            Condition::Fill => is_filling,
+            Condition::NotFill => !is_filling,
+        }
+    }
+
+    pub fn next (&self) -> Self {
+        match self {
+            Condition::Always => Condition::Fill,
+            Condition::Fill => Condition::NotFill,
+            Condition::NotFill => Condition::Always,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
 
 pub struct SeqStep {
     pub note: Option<f32>,
+    pub chord: [Option<f32>; CHORD_NOTES],
+    pub velocity: Option<f32>, // accents, the default velocity otherwise
     pub model: Option<usize>,
     pub harmonic: Option<f32>,
     pub timbre: Option<f32>,
     pub morph: Option<f32>,
     pub decay: Option<f32>,
     pub gate_length: Option<f64>,
+    pub glide: Option<f32>,
+    pub volume: Option<f32>,
+    pub pan: Option<f32>,
+    pub balance: Option<f32>,
+    pub lpg_colour: Option<f32>,
+    pub condition: Condition,
+    pub slide: bool, // tie from the previous note and glide to this one, without a new trigger
+    pub offset: f64, // fraction of a step the note is played off the grid, from live recording or a nudge
     pub is_awaiting_note: bool
 }
 
+/// Song position shared by every voice, counted in ticks of `PPQN` per quarter note from the start of the song.
+/// Bars are `beats_per_bar` quarter notes long.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub tick: f64,
+    pub beats_per_bar: u32,
+}
+
+impl Default for Clock {
+    fn default () -> Self {
+        Self { tick: 0., beats_per_bar: BEATS_PER_BAR }
+    }
+}
+
+impl Clock {
+    pub fn ticks_per_sample (tempo: f32) -> f64 {
+        tempo as f64 * PPQN as f64 / (60. * SAMPLE_RATE as f64)
+    }
+
+    pub fn advance (&mut self, samples: usize, tempo: f32) {
+        self.tick += samples as f64 * Clock::ticks_per_sample(tempo);
+    }
+
+    /// Tick the bar starts at, bars counted from 1.
+    pub fn bar_tick (&self, bar: u32) -> f64 {
+        (bar.max(1) - 1) as f64 * (self.beats_per_bar * PPQN) as f64
+    }
+
+    /// Position as bars, beats and ticks, the first two counted from 1.
+    pub fn bar_beat_tick (&self) -> (u32, u32, u32) {
+        let tick = self.tick as u32;
+        let beat = tick / PPQN;
+        (beat / self.beats_per_bar + 1, beat % self.beats_per_bar + 1, tick % PPQN)
+    }
+}
+
+impl SeqStep {
+    /// Stacks a note on the step, unless it is already sounding or the chord is full.
+    pub fn add_chord_note (&mut self, note: f32) {
+        if self.note == Some(note) || self.chord.contains(&Some(note)) { return; }
+        if let Some(free) = self.chord.iter_mut().find(|chord_note| chord_note.is_none()) {
+            *free = Some(note);
+        }
+    }
+
+    pub fn has_locks (&self) -> bool {
+        self.model.is_some() || self.harmonic.is_some() || self.timbre.is_some() || self.morph.is_some() ||
+        self.decay.is_some() || self.gate_length.is_some() || self.glide.is_some() ||
+        self.volume.is_some() || self.pan.is_some() || self.balance.is_some() || self.lpg_colour.is_some() ||
+        self.velocity.is_some()
+    }
+}
+
+/// Accelerando or ritardando, from the tempo at the start bar to the one reached `bars` later.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoRamp {
+    pub start_bar: u32,
+    pub bars: u32,
+    pub from: f32,
+    pub to: f32,
+}
+
+impl TempoRamp {
+    fn start_tick (&self, clock: &Clock) -> f64 { clock.bar_tick(self.start_bar) }
+    fn end_tick (&self, clock: &Clock) -> f64 { clock.bar_tick(self.start_bar + self.bars) }
+
+    fn tempo_at (&self, clock: &Clock) -> f32 {
+        let (start, end) = (self.start_tick(clock), self.end_tick(clock));
+        let progress = ((clock.tick - start) / (end - start)).clamp(0., 1.);
+        self.from + (self.to - self.from) * progress as f32
+    }
+}
+
+/// What undo brings back of the transport, which all voices share.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoSnapshot {
+    tempo: f32,
+    tempo_automation: Vec<TempoRamp>,
+}
+
 pub struct Sequencer {
     pub tempo: f32,
-    pub status: SeqStatus
+    pub status: SeqStatus,
+    pub clock: Clock,
+    pub tempo_automation: Vec<TempoRamp>, // part of the song, in bars
+    pub tempo_ramp_bars: u32,
+    pub tempo_entry: Option<u32>,
+    taps: Vec<Instant>,
+    pub metronome: Metronome,
+    pub count_in_bars: u32,
+    count_in: Clock,
+    pub humanize: f32,
+    pub is_filling: bool,
+    pub voice_allocator: VoiceAllocator,
+    pub key_zones: KeyZones,
+    history: History<TempoSnapshot>,
 }
 
 impl Sequencer {
+    pub fn new (tempo: f32) -> Self {
+        Self {
+            tempo,
+            status: SeqStatus::Stop,
+            clock: Clock::default(),
+            tempo_automation: Vec::new(),
+            tempo_ramp_bars: 0,
+            tempo_entry: None,
+            taps: Vec::new(),
+            metronome: Metronome::default(),
+            count_in_bars: 1,
+            count_in: Clock::default(),
+            humanize: 0.,
+            is_filling: false,
+            voice_allocator: VoiceAllocator::new(),
+            key_zones: KeyZones::new(),
+            history: History::new(),
+        }
+    }
+
     pub fn tempo_up (seq: &mut MutexGuard<Sequencer>) {
-        seq.tempo += 4.0;
+        seq.tempo = (seq.tempo + 4.0).min(MAX_TEMPO);
     }
     
     pub fn tempo_down (seq: &mut MutexGuard<Sequencer>) {
-        seq.tempo -= 4.0;
+        seq.tempo = (seq.tempo - 4.0).max(MIN_TEMPO);
+    }
+
+    /// Sets the tempo from the average interval between the last few taps.
+    pub fn tap_tempo (&mut self) {
+        let now = Instant::now();
+        if self.taps.last().is_some_and(|&tap| now.duration_since(tap) > TAP_TIMEOUT) {
+            self.taps.clear();
+        }
+        self.taps.push(now);
+        if self.taps.len() > TAP_COUNT { self.taps.remove(0); }
+        if self.taps.len() < 2 { return; }
+        let interval = self.taps.last().unwrap().duration_since(self.taps[0]).as_secs_f32() / (self.taps.len() - 1) as f32;
+        self.tempo = (60. / interval).round().clamp(MIN_TEMPO, MAX_TEMPO);
+    }
+
+    pub fn start_tempo_entry (&mut self) { self.tempo_entry = Some(0); }
+
+    pub fn tempo_entry_digit (&mut self, digit: u32) {
+        self.tempo_entry = self.tempo_entry.map(|entry| (entry * 10 + digit).min(MAX_TEMPO as u32));
+    }
+
+    pub fn tempo_entry_delete (&mut self) {
+        self.tempo_entry = self.tempo_entry.map(|entry| entry / 10);
+    }
+
+    /// Goes to the entered tempo, straight away or by a ramp starting on the next bar.
+    /// Confirming an empty entry clears the tempo automation instead.
+    pub fn confirm_tempo_entry (&mut self) {
+        let Some(entry) = self.tempo_entry.take() else { return };
+        if entry == 0 {
+            self.tempo_automation.clear();
+            return;
+        }
+        let tempo = (entry as f32).clamp(MIN_TEMPO, MAX_TEMPO);
+        if self.tempo_ramp_bars == 0 {
+            self.tempo = tempo;
+            return;
+        }
+        let (bar, _, _) = self.clock.bar_beat_tick();
+        let ramp = TempoRamp { start_bar: bar + 1, bars: self.tempo_ramp_bars, from: self.tempo, to: tempo };
+        self.tempo_automation.retain(|other| other.start_bar + other.bars <= ramp.start_bar || other.start_bar >= ramp.start_bar + ramp.bars);
+        self.tempo_automation.push(ramp);
+        self.tempo_automation.sort_by_key(|ramp| ramp.start_bar);
+    }
+
+    pub fn snapshot (&self) -> TempoSnapshot {
+        TempoSnapshot { tempo: self.tempo, tempo_automation: self.tempo_automation.clone() }
+    }
+
+    fn restore (&mut self, snapshot: TempoSnapshot) {
+        self.tempo = snapshot.tempo;
+        self.tempo_automation = snapshot.tempo_automation;
+    }
+
+    /// Adds the command that just ran to the tempo history, if it changed the tempo or its automation.
+    pub fn commit_history (&mut self, before: TempoSnapshot) {
+        let after = self.snapshot();
+        self.history.commit(before, &after);
+    }
+
+    pub fn undo (&mut self) {
+        let current = self.snapshot();
+        if let Some(previous) = self.history.undo(current) { self.restore(previous); }
+    }
+
+    pub fn redo (&mut self) {
+        let current = self.snapshot();
+        if let Some(next) = self.history.redo(current) { self.restore(next); }
+    }
+
+    pub fn undo_command (&self) -> Option<u64> { self.history.undo_command() }
+    pub fn redo_command (&self) -> Option<u64> { self.history.redo_command() }
+
+    pub fn tempo_ramp_bars_up   (&mut self) { self.tempo_ramp_bars = (self.tempo_ramp_bars + 1).min(MAX_TEMPO_RAMP_BARS); }
+    pub fn tempo_ramp_bars_down (&mut self) { self.tempo_ramp_bars = self.tempo_ramp_bars.saturating_sub(1); }
+
+    /// Moves the song position on by `samples`, following the tempo automation on the way.
+    /// During a count-in only the count-in moves, and recording starts within the block the count-in ends in.
+    pub fn advance (&mut self, samples: usize) {
+        if self.is_counting_in() {
+            self.count_in.advance(samples, self.tempo);
+            let remaining = self.count_in.bar_tick(self.count_in_bars + 1) - self.count_in.tick;
+            if remaining < samples as f64 * Clock::ticks_per_sample(self.tempo) {
+                self.clock.tick -= remaining;
+                self.set_status(SeqStatus::Recording);
+            }
+            return;
+        }
+        let from = self.clock.tick;
+        self.clock.advance(samples, self.tempo);
+        let to = self.clock.tick;
+        if let Some(ramp) = self.tempo_automation.iter().find(|ramp| ramp.start_tick(&self.clock) < to && ramp.end_tick(&self.clock) > from) {
+            self.tempo = ramp.tempo_at(&self.clock);
+        }
+    }
+
+    /// Clock the metronome and the position display follow: the count-in while there is one, else the song.
+    pub fn current_clock (&self) -> &Clock {
+        if self.is_counting_in() { &self.count_in } else { &self.clock }
+    }
+
+    pub fn render_metronome (&mut self, out: &mut [f32]) {
+        let is_counting = self.is_counting_in() || self.metronome.is_on && self.is_running();
+        let clock = *self.current_clock();
+        self.metronome.render(&clock, self.tempo, is_counting, out);
     }
 
-    pub fn start_recording (&mut self) {
-        self.status = SeqStatus::Recording;
+    pub fn humanize_up   (&mut self) { inc_f32(&mut self.humanize); }
+    pub fn humanize_down (&mut self) { dec_f32(&mut self.humanize); }
+
+    pub fn toggle_metronome (&mut self) { self.metronome.is_on = !self.metronome.is_on; }
+
+    pub fn count_in_bars_cycle (&mut self) { self.count_in_bars = (self.count_in_bars + 1) % (MAX_COUNT_IN_BARS + 1); }
+
+    pub fn next_time_signature (&mut self) {
+        let beats_per_bar = self.clock.beats_per_bar % MAX_BEATS_PER_BAR + 1;
+        self.clock.beats_per_bar = beats_per_bar.max(MIN_BEATS_PER_BAR);
+        self.count_in.beats_per_bar = self.clock.beats_per_bar;
+    }
+
+    /// Starts recording, after the metronome has counted in when `count_in` is asked for and enabled.
+    pub fn start_recording (&mut self, count_in: bool) {
+        if count_in && self.count_in_bars > 0 {
+            self.count_in.tick = 0.;
+            self.set_status(SeqStatus::CountIn);
+        }
+        else {
+            self.set_status(SeqStatus::Recording);
+        }
     }
     pub fn play_pause(&mut self) {
-        self.status = match self.status {
-            SeqStatus::Recording => SeqStatus::Play,
-            SeqStatus::Play => SeqStatus::Stop,
-            SeqStatus::Stop => SeqStatus::Play,
+        match self.status {
+            SeqStatus::Recording => self.set_status(SeqStatus::Play),
+            SeqStatus::CountIn => self.pause(),
+            SeqStatus::Overdub | SeqStatus::Play => self.pause(),
+            SeqStatus::Pause | SeqStatus::Stop => self.resume(),
         };
     }
+    pub fn toggle_overdub(&mut self) {
+        match self.status {
+            SeqStatus::Overdub => self.set_status(SeqStatus::Play),
+            _ => self.set_status(SeqStatus::Overdub),
+        };
+    }
+
+    /// Plays from the top of the song.
+    pub fn start(&mut self) {
+        self.clock.tick = 0.;
+        self.set_status(SeqStatus::Play);
+    }
+    /// Halts playback and rewinds to the top of the song.
+    pub fn stop(&mut self) {
+        self.clock.tick = 0.;
+        self.set_status(SeqStatus::Stop);
+    }
+    /// Plays on from wherever the clock was paused or located.
+    pub fn resume(&mut self) {
+        self.set_status(SeqStatus::Play);
+    }
+    /// Halts playback, keeping the position.
+    pub fn pause(&mut self) {
+        self.set_status(SeqStatus::Pause);
+    }
+    pub fn locate(&mut self, bar: u32) {
+        self.clock.tick = self.clock.bar_tick(bar);
+    }
+    pub fn locate_previous_bar(&mut self) {
+        let (bar, _, _) = self.clock.bar_beat_tick();
+        self.locate(bar.saturating_sub(1));
+    }
+    pub fn locate_next_bar(&mut self) {
+        let (bar, _, _) = self.clock.bar_beat_tick();
+        self.locate(bar + 1);
+    }
+
+    fn set_status(&mut self, status: SeqStatus) {
+        self.status = status;
+        TRANSPORT.update(self.is_running());
+    }
 
+    pub fn is_counting_in(&self) -> bool { self.status == SeqStatus::CountIn }
     pub fn is_recording(&self) -> bool { self.status == SeqStatus::Recording }
+    pub fn is_overdubbing(&self) -> bool { self.status == SeqStatus::Overdub }
     pub fn is_playing(&self) -> bool { self.status == SeqStatus::Play }
+    pub fn is_paused(&self) -> bool { self.status == SeqStatus::Pause }
     pub fn is_stopped(&self) -> bool { self.status == SeqStatus::Stop }
+    /// Whether the clock is moving, which is the case while recording too.
+    pub fn is_running(&self) -> bool { !self.is_paused() && !self.is_stopped() }
 
 }
 
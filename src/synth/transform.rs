@@ -0,0 +1,49 @@
+use crate::synth::scale::{degree2note, note2degree};
+use crate::synth::seq::SeqStep;
+use crate::synth::util::Rng;
+
+pub fn reverse (steps: &mut [SeqStep]) {
+    steps.reverse();
+}
+
+/// Rotates the pattern by `amount` steps, positive values move every step later.
+pub fn rotate (steps: &mut [SeqStep], amount: isize) {
+    if steps.is_empty() { return; }
+    let amount = amount.rem_euclid(steps.len() as isize) as usize;
+    steps.rotate_right(amount);
+}
+
+/// Mirrors every note around `axis` in scale degrees, so the result stays in the porcupine mode.
+pub fn invert (steps: &mut [SeqStep], axis: i32) {
+    for step in steps.iter_mut() {
+        let mirror = |note: f32| degree2note(2 * axis - note2degree(note));
+        step.note = step.note.map(mirror);
+        step.chord = step.chord.map(|chord_note| chord_note.map(mirror));
+    }
+}
+
+pub fn retrograde_inversion (steps: &mut [SeqStep], axis: i32) {
+    reverse(steps);
+    invert(steps, axis);
+}
+
+/// Doubles the length of the pattern by following every step with a rest.
+pub fn stretch (steps: &mut Vec<SeqStep>) {
+    *steps = steps.iter()
+        .flat_map(|&step| [step, SeqStep::default()])
+        .collect();
+}
+
+/// Halves the length of the pattern by dropping every other step.
+pub fn shrink (steps: &mut Vec<SeqStep>) {
+    *steps = steps.iter()
+        .step_by(2)
+        .copied()
+        .collect();
+}
+
+pub fn shuffle (steps: &mut [SeqStep], rng: &mut Rng) {
+    for i in (1..steps.len()).rev() {
+        steps.swap(i, rng.below(i + 1));
+    }
+}
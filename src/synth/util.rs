@@ -3,4 +3,41 @@ pub fn inc_usize (value: &mut usize) { *value = (*value + 1).min(23); }
 pub fn dec_f32 (value: &mut f32) { *value = (*value - 0.1).max(0.); }
 pub fn inc_f32 (value: &mut f32) { *value = (*value + 0.1).min(1.); }
 pub fn dec_f64 (value: &mut f64) { *value = (*value - 0.1).max(0.); }
-pub fn inc_f64 (value: &mut f64) { *value = (*value + 0.1).min(1.); }
\ No newline at end of file
+pub fn inc_f64 (value: &mut f64) { *value = (*value + 0.1).min(1.); }
+
+/// Stable pseudo-random value in `-1..1` for a seed and an index: the same every time it is asked for.
+pub fn jitter (seed: u64, index: i64) -> f32 {
+    let mut x = seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    (x >> 40) as f32 / (1u64 << 23) as f32 - 1.
+}
+
+/// Xorshift generator, plenty for musical randomness and free of extra dependencies.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new () -> Self {
+        let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
+        Self(seed | 1)
+    }
+
+    pub fn next_u64 (&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform integer in `0..n`.
+    pub fn below (&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+
+    /// Uniform float in `0..1`.
+    pub fn unit (&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -1,10 +1,13 @@
-use crate::keyboard_utils::{ch, VK_Comma, VK_LeftBracket, VK_RightBracket, VK_B, VK_C, VK_D, VK_E, VK_F, VK_G, VK_H, VK_I, VK_J, VK_K, VK_M, VK_N, VK_O, VK_R, VK_S, VK_T, VK_U, VK_V, VK_W, VK_X, VK_Y, VK_Z};
+use crate::keyboard_utils::{ch, VK_A, VK_BackQuote, VK_BackSlash, VK_Comma, VK_LeftBracket, VK_RightBracket, VK_Slash, VK_B, VK_C, VK_D, VK_E, VK_Equal, VK_F, VK_G, VK_H, VK_I, VK_J, VK_K, VK_L, VK_M, VK_N, VK_O, VK_P, VK_Q, VK_Quote, VK_R, VK_S, VK_SemiColon, VK_T, VK_U, VK_V, VK_W, VK_X, VK_Y, VK_Z};
 
 use std::collections::HashMap;
-use std::sync::{MutexGuard, LazyLock, Arc, Mutex};
+use std::fmt::Write;
+use std::sync::{atomic::Ordering, LazyLock, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use crate::synth::part::Synth;
 use crate::synth::seq::*;
-use crate::{VOICES, SELECTED_VOICE};
+use crate::{set_voice_count, voices, SELECTED_VOICE};
 use rdev::{Event, Key, EventType::{KeyPress, KeyRelease}};
 
 pub static KEY_NOTES: LazyLock<HashMap<Key, i32>> = LazyLock::new(|| HashMap::from([
@@ -14,78 +17,331 @@ pub static KEY_NOTES: LazyLock<HashMap<Key, i32>> = LazyLock::new(|| HashMap::fr
     (Key::KeyZ,00), (Key::KeyX,03), (Key::KeyC,06), (Key::KeyV,09), (Key::KeyB,12), (Key::KeyN,15), (Key::KeyM,18), (Key::Comma,21), 
 ]));
 
-pub fn print_info (seq: &MutexGuard<Sequencer>, synth: &MutexGuard<Synth>) {
+pub fn print_info (seq: &Sequencer, synth: &Synth) {
+    print_screen(&format_info(seq, synth));
+}
+
+fn print_screen (info: &str) {
     clearscreen::clear().unwrap();
-    println!("[ F1-F2 ]    Model: {}", ENGINE_DESCRIPIONS[synth.patch.engine]);
-    println!("[ F3-F4 ] Harmonic: {}", (10. * synth.target_harmonic).round() / 10.);
-    println!("[ F5-F6 ]   Timbre: {}", (10. * synth.target_timbre).round() / 10.);
-    println!("[ F7-F8 ]    Morph: {}", (10. * synth.target_morph).round() / 10.);
-    println!("[ F9-10 ]    Decay: {}", (10. * synth.patch.decay).round() / 10.);
-    println!("                           +----------+------------+--------------+");
-    println!("                           |  {} Rest  |  {} {}  |  BKSP Clear  |", 
+    print!("{}", info);
+}
+
+/// Everything the screen shows, built up front so that it can be printed once the locks are let go.
+fn format_info (seq: &Sequencer, synth: &Synth) -> String {
+    let mut out = String::new();
+    writeln!(out, "[ F1-F2 ]    Model: {}", ENGINE_DESCRIPIONS[synth.patch.engine]).unwrap();
+    writeln!(out, "[ F3-F4 ] Harmonic: {}", (10. * synth.target_harmonic).round() / 10.).unwrap();
+    writeln!(out, "[ F5-F6 ]   Timbre: {}", (10. * synth.target_timbre).round() / 10.).unwrap();
+    writeln!(out, "[ F7-F8 ]    Morph: {}", (10. * synth.target_morph).round() / 10.).unwrap();
+    writeln!(out, "[ F9-10 ]    Decay: {}", (10. * synth.patch.decay).round() / 10.).unwrap();
+    writeln!(out, "[ RSHIFT F1-F2 ] Volume: {}   [ RSHIFT F3-F4 ] Pan: {}   [ RSHIFT F5-F6 ] Aux balance: {}   [ RSHIFT F7-F8 ] LPG colour: {}",
+        (10. * synth.volume).round() / 10., (10. * synth.pan).round() / 10.,
+        (10. * synth.balance).round() / 10., (10. * synth.patch.lpg_colour).round() / 10.,
+    ).unwrap();
+    writeln!(out, "[ {} / {} ] Scene A <-> B: {}   [ RSHIFT {} / {} ] Store scene A: {}  B: {}",
+        ch(VK_Q), ch(VK_A), (10. * synth.target_scene_morph).round() / 10., ch(VK_Q), ch(VK_A),
+        if synth.has_scene(0) {"Stored"} else {"Empty"}, if synth.has_scene(1) {"Stored"} else {"Empty"},
+    ).unwrap();
+    writeln!(out, "[ RSHIFT F9-10 ] Glide: {} s   [ {} ] Slide: {}   [ RSHIFT {} ] Legato: {}   [ RCTRL {} ] Note priority: {:?}", 
+        (10. * synth.glide_time).round() / 10., ch(VK_Slash), if synth.slide {"On"} else {"Off"},
+        ch(VK_Slash), if synth.legato {"On"} else {"Off"}, ch(VK_Slash), synth.note_priority,
+    ).unwrap();
+    writeln!(out, "                           +----------+------------+--------------+").unwrap();
+    writeln!(out, "                           |  {} Rest  |  {} {}  |  BKSP Clear  |", 
         ch(VK_LeftBracket), ch(VK_RightBracket),
         if seq.is_recording() {"Record"} else {"  Undo"},
-    );
-    println!("+--------------------------+----------+------------+--------------+");
-    println!("|    {}   {}   {}   {}   {}   {}   {}       3   4   5   6   7   8   9    |", 
+    ).unwrap();
+    writeln!(out, "+--------------------------+----------+------------+--------------+").unwrap();
+    writeln!(out, "|    {}   {}   {}   {}   {}   {}   {}       3   4   5   6   7   8   9    |", 
         ch(VK_S), ch(VK_D), ch(VK_F), ch(VK_G), ch(VK_H), ch(VK_J), ch(VK_K),
-    );
-    println!("|  {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}  |",
+    ).unwrap();
+    writeln!(out, "|  {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}   {}  |",
         ch(VK_Z), ch(VK_X), ch(VK_C), ch(VK_V), ch(VK_B), ch(VK_N), ch(VK_M), ch(VK_Comma),
         ch(VK_W), ch(VK_E), ch(VK_R), ch(VK_T), ch(VK_Y), ch(VK_U), ch(VK_I), ch(VK_O),
-    );
-    println!("+-----------------------------------------------------------------+");
-    println!("[ LSHIFT  > ] Pitch Bend    [ LCTRL ] Vibrato   [ . - ]  Octave: {} ", synth.info_octave);
-    println!("                     [   SPACE_BAR    {:?}   ]",
-        if seq.is_playing() {"Play"} else {"Stop"}
-    );
-    print_sequence(&synth, &seq);
-    println!("                 Transport: {:?}", seq.status);
-    println!("[   Up / Down  ] Tempo: {} BPM", seq.tempo.round());
-    println!("[ Left / Right ] Gate length: {}", (10. * synth.gate_length).round() / 10.);
-    println!("");
-    println!("(Press [Esc] to exit)");
+    ).unwrap();
+    writeln!(out, "+-----------------------------------------------------------------+").unwrap();
+    writeln!(out, "[ RSHIFT + note ] Accent").unwrap();
+    writeln!(out, "[ LSHIFT  > ] Pitch Bend    [ LCTRL ] Vibrato   [ . - ]  Octave: {} ", synth.info_octave).unwrap();
+    writeln!(out, "                     [   SPACE_BAR    {:?}   ]   [ F11 ] Stop   [ F12 ] Start   [ RSHIFT F11-F12 ] Locate bar",
+        if seq.is_running() {"Pause"} else {"Play"}
+    ).unwrap();
+    write_voices(&mut out, seq, synth);
+    write_sequence(&mut out, synth, seq);
+    let (bar, beat, tick) = seq.current_clock().bar_beat_tick();
+    writeln!(out, "                 Transport: {:?}   {}.{}.{:02}", seq.status, bar, beat, tick).unwrap();
+    writeln!(out, "[   Up / Down  ] Tempo: {} BPM   [ RETURN ] Tap   [ RSHIFT RETURN ] Type BPM{}", seq.tempo.round(),
+        seq.tempo_entry.map_or(String::new(), |entry| format!(": {}_  ([ RETURN ] to confirm)", if entry > 0 {entry.to_string()} else {String::new()})),
+    ).unwrap();
+    writeln!(out, "[ RSHIFT Up / Down ] Tempo ramp: {}   Automation: {}", 
+        if seq.tempo_ramp_bars == 0 {String::from("Off")} else {format!("{} bars", seq.tempo_ramp_bars)},
+        if seq.tempo_automation.is_empty() {String::from("None")} else {
+            seq.tempo_automation.iter()
+                .map(|ramp| format!("bar {}-{}: {} > {} BPM", ramp.start_bar, ramp.start_bar + ramp.bars, ramp.from.round(), ramp.to.round()))
+                .collect::<Vec<_>>().join(", ")
+        },
+    ).unwrap();
+    writeln!(out, "[ Left / Right ] Gate length: {}", (10. * synth.gate_length).round() / 10.).unwrap();
+    writeln!(out, "[      TAB     ] Record mode: {:?}", synth.record_mode).unwrap();
+    writeln!(out, "[       {}      ] Metronome: {}   [ RSHIFT {} ] Count-in: {} bars   [ {} ] Time signature: {}/4",
+        ch(VK_P), if seq.metronome.is_on {"On"} else {"Off"}, ch(VK_P), seq.count_in_bars, ch(VK_L), seq.clock.beats_per_bar,
+    ).unwrap();
+    writeln!(out, "[       0      ] Overdub: {}", if seq.is_overdubbing() {"On"} else {"Off"}).unwrap();
+    writeln!(out, "[    NUMPAD    ] 4-6 Rotate   8 Reverse   2 Invert   5 Retrograde-inversion   * Stretch   / Shrink   0 Shuffle").unwrap();
+    writeln!(out, "[   NUMPAD 7   ] Markov melody (RSHIFT: learn from all voices)   [ NUMPAD - + ] Length: {} steps", 
+        synth.generator_length,
+    ).unwrap();
+    writeln!(out, "[   NUMPAD 9   ] Turing machine: {} (RSHIFT: freeze)   [ NUMPAD 1-3 ] Lock: {}   [ RSHIFT NUMPAD 1-3 ] Length: {} steps",
+        if synth.is_turing_running() {"On"} else {"Off"}, (10. * synth.turing.lock).round() / 10., synth.turing.length,
+    ).unwrap();
+    let (loop_start, loop_end) = synth.loop_bounds();
+    writeln!(out, "[       {}      ] Direction: {:?}   Loop: {}", ch(VK_SemiColon), synth.direction,
+        if synth.has_loop() {format!("steps {}-{}   [ RSHIFT {} ] Clear", loop_start + 1, loop_end + 1, ch(VK_SemiColon))} else {String::from("Whole pattern")},
+    ).unwrap();
+    writeln!(out, "[ NUMPAD ENTER ] Polyphony: {:?}", seq.voice_allocator.mode).unwrap();
+    writeln!(out, "[ RCTRL NUMPAD ENTER ] Zones: {:?}{}   [ RCTRL {} ] Zone voice: {}   [ RCTRL Left / Right ] Zone interval: {:+} steps",
+        seq.key_zones.mode, if seq.voice_allocator.is_poly() && seq.key_zones.is_on() {" (off while polyphonic)"} else {""},
+        ch(VK_Equal), seq.key_zones.voice % voices().len() + 1, seq.key_zones.interval,
+    ).unwrap();
+    writeln!(out, "[     HOME     ] Step editor: {}", if synth.is_editing() {"On"} else {"Off"}).unwrap();
+    if synth.is_editing() {
+        writeln!(out, "[ RSHIFT PGUP / PGDN ] Loop start / end at cursor").unwrap();
+        writeln!(out, "[ RSHIFT Left / Right ] Nudge step   [ RSHIFT {} ] Step condition", ch(VK_BackSlash)).unwrap();
+        writeln!(out, "[ PGUP / PGDN  ] Move cursor   [ INS ] Insert   [ DEL ] Delete   [ END ] Duplicate   [ {} ] Clear locks", 
+            ch(VK_BackSlash),
+        ).unwrap();
+    }
+    writeln!(out, "[     1 - 2    ] Quantize: {}%   [ RSHIFT 1 - 2 ] Humanize: {}%   [ RCTRL 1 - 2 ] Velocity: {}", 
+        (100. * synth.quantize).round(), (100. * seq.humanize).round(), (10. * synth.velocity).round() / 10.,
+    ).unwrap();
+    writeln!(out, "[       {}      ] Undo{}   [ RSHIFT {} ] Redo{}", 
+        ch(VK_BackQuote), if synth.can_undo(seq) {""} else {" (empty)"},
+        ch(VK_BackQuote), if synth.can_redo(seq) {""} else {" (empty)"},
+    ).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "(Press [Esc] to exit)").unwrap();
+    out
+}
+
+/// Mute and solo state of every voice, the selected one read through `synth` as the caller already holds it.
+fn write_voices (out: &mut String, seq: &Sequencer, synth: &Synth) {
+    writeln!(out, "[ {} ] Next voice   [ RSHIFT {} ] Previous voice   [ RCTRL Up / Down ] Voices: {}   [ RCTRL F1-F12 ] Mute   [ RCTRL RSHIFT F1-F12 ] Solo   [ RCTRL (RSHIFT) 0 ] Mute (solo) selected   [ {} hold ] Fill: {}", 
+        ch(VK_Equal), ch(VK_Equal), voices().len(), ch(VK_Quote), if seq.is_filling {"On"} else {"Off"},
+    ).unwrap();
+    for (i, voice) in voices().iter().enumerate() {
+        let selected = i == selected_voice();
+        let guard = if selected { None } else { Some(voice.lock().unwrap()) };
+        let voice = guard.as_deref().unwrap_or(synth);
+        writeln!(out, "{} Voice {}: {:<26} {:>3} steps   {:<8} {:<5} Vol: {}   Pan: {}",
+            if selected {">"} else {" "}, i + 1, ENGINE_DESCRIPIONS[voice.patch.engine], voice.seq_notes.len(), 
+            format!("{:?}", voice.direction), if voice.is_soloed {"Solo"} else if voice.is_muted {"Mute"} else {"On"},
+            (10. * voice.volume).round() / 10., (10. * voice.pan).round() / 10.,
+        ).unwrap();
+    }
 }
 
-pub fn print_sequence (synth: &MutexGuard<Synth>, seq: &MutexGuard<Sequencer>) {
+fn write_sequence (out: &mut String, synth: &Synth, seq: &Sequencer) {
     if seq.is_recording() || !synth.seq_notes.is_empty() {
-        println!("");
-        println!("Sequence: ");
+        writeln!(out).unwrap();
+        writeln!(out, "Sequence: ").unwrap();
     }
     if seq.is_recording() && synth.seq_notes.is_empty() {
-        println!("[ ]");
+        writeln!(out, "[ ]").unwrap();
     }
     for (i, sq) in synth.seq_notes.iter().enumerate() {
-        if i % 8 == 0 { print!("["); }
+        if i % 8 == 0 { write!(out, "[").unwrap(); }
+        write!(out, "{}", 
+            if synth.cursor == Some(i) {">"} else if sq.slide {"~"} else if sq.velocity.is_some() {"!"} 
+            else if sq.condition != Condition::Always {"?"} else {" "}
+        ).unwrap();
         match sq {
-            SeqStep { is_awaiting_note: true, ..  } => print!("   MOD  "),
-            SeqStep { note: None, model: None, harmonic: None, timbre: None, morph: None, gate_length: None, decay: None, .. } 
-                => print!(" (     )"),
-            SeqStep { note: None, .. } => print!(" ( MOD )"),
-            SeqStep { note: Some(note), model: None, harmonic: None, timbre: None, morph: None, gate_length: None, decay: None, .. } 
-                => print!(" ({:>+0width$.prec$})", note, width=5, prec=1),
-            SeqStep { note: Some(note), .. } => print!(" MOD({:>+0width$.prec$})", note, width=5, prec=1),
+            SeqStep { is_awaiting_note: true, ..  } => write!(out, "  MOD  ").unwrap(),
+            SeqStep { note: None, .. } if !sq.has_locks() => write!(out, "(     )").unwrap(),
+            SeqStep { note: None, .. } => write!(out, "( MOD )").unwrap(),
+            SeqStep { note: Some(note), .. } if !sq.has_locks() => write!(out, "({:>+0width$.prec$})", note, width=5, prec=1).unwrap(),
+            SeqStep { note: Some(note), .. } => write!(out, "MOD({:>+0width$.prec$})", note, width=5, prec=1).unwrap(),
         };
-        if i % 8 == 7 || (i+1) == synth.seq_notes.len() { println!(" ]"); }
+        if i % 8 == 7 || (i+1) == synth.seq_notes.len() { writeln!(out, " ]").unwrap(); }
+    }
+    if let Some((cursor, step)) = synth.cursor.and_then(|cursor| Some((cursor, synth.seq_notes.get(cursor)?))) {
+        write_step_locks(out, cursor, step);
+    }
+}
+
+fn write_step_locks (out: &mut String, index: usize, step: &SeqStep) {
+    let round = |value: f32| (10. * value).round() / 10.;
+    let locks = [
+        ("Model", step.model.map(|model| ENGINE_DESCRIPIONS[model].to_string())),
+        ("Harmonic", step.harmonic.map(|harmonic| round(harmonic).to_string())),
+        ("Timbre", step.timbre.map(|timbre| round(timbre).to_string())),
+        ("Morph", step.morph.map(|morph| round(morph).to_string())),
+        ("Decay", step.decay.map(|decay| round(decay).to_string())),
+        ("Gate", step.gate_length.map(|gate_length| round(gate_length as f32).to_string())),
+        ("Glide", step.glide.map(|glide| round(glide).to_string())),
+        ("Velocity", step.velocity.map(|velocity| round(velocity).to_string())),
+        ("Volume", step.volume.map(|volume| round(volume).to_string())),
+        ("Pan", step.pan.map(|pan| round(pan).to_string())),
+        ("Balance", step.balance.map(|balance| round(balance).to_string())),
+        ("LPG colour", step.lpg_colour.map(|lpg_colour| round(lpg_colour).to_string())),
+    ];
+    write!(out, "Step {}:  {}", index + 1, step.note.map_or(String::from("Rest"), |note| format!("Note: {:+.1}", note))).unwrap();
+    for chord_note in step.chord.iter().flatten() { write!(out, " {:+.1}", chord_note).unwrap(); }
+    for (name, value) in locks {
+        if let Some(value) = value { write!(out, "  {}: {}", name, value).unwrap(); }
+    }
+    if step.offset != 0. { write!(out, "  Nudge: {:+.2}", step.offset).unwrap(); }
+    if step.condition != Condition::Always { write!(out, "  Condition: {:?}", step.condition).unwrap(); }
+    writeln!(out, "{}", if step.slide {"  Slide"} else {""}).unwrap();
+}
+
+const TRANSPORT_REFRESH: Duration = Duration::from_millis(20);
+
+/// Sleeps while the transport is halted, and redraws the info on every beat while it runs.
+pub fn transport_loop (seq: Arc<Mutex<Sequencer>>) {
+    let mut last_beat = (0, 0);
+    loop {
+        drop(TRANSPORT.condvar.wait_while(
+            TRANSPORT.is_playing.lock().unwrap(), |is_playing| !*is_playing
+        ).unwrap());
+        thread::sleep(TRANSPORT_REFRESH);
+        let info = {
+            let seq = seq.lock().unwrap();
+            let (bar, beat, _) = seq.current_clock().bar_beat_tick();
+            if !seq.is_running() || (bar, beat) == last_beat { continue; }
+            last_beat = (bar, beat);
+            format_info(&seq, &voices().get(selected_voice()).unwrap().lock().unwrap())
+        };
+        // printing to the terminal is slow, and the audio thread needs the sequencer for every block
+        print_screen(&info);
+    }
+}
+
+fn key2digit (key: Key) -> Option<u32> {
+    match key {
+        Key::Num0 | Key::Kp0 => Some(0), Key::Num1 | Key::Kp1 => Some(1), Key::Num2 | Key::Kp2 => Some(2),
+        Key::Num3 | Key::Kp3 => Some(3), Key::Num4 | Key::Kp4 => Some(4), Key::Num5 | Key::Kp5 => Some(5),
+        Key::Num6 | Key::Kp6 => Some(6), Key::Num7 | Key::Kp7 => Some(7), Key::Num8 | Key::Kp8 => Some(8),
+        Key::Num9 | Key::Kp9 => Some(9),
+        _ => None,
+    }
+}
+
+fn key2voice (key: Key) -> Option<usize> {
+    let voice = match key {
+        Key::F1 => 0, Key::F2 => 1, Key::F3 => 2, Key::F4 => 3, Key::F5 => 4, Key::F6 => 5,
+        Key::F7 => 6, Key::F8 => 7, Key::F9 => 8, Key::F10 => 9, Key::F11 => 10, Key::F12 => 11,
+        _ => return None,
+    };
+    (voice < voices().len()).then_some(voice)
+}
+
+fn selected_voice () -> usize { SELECTED_VOICE.load(Ordering::Relaxed) }
+
+/// Moves the selection by `offset` voices, wrapping around.
+fn select_voice (offset: isize) {
+    let voice = (selected_voice() as isize + offset).rem_euclid(voices().len() as isize) as usize;
+    SELECTED_VOICE.store(voice, Ordering::Relaxed);
+}
+
+/// Runs `action` on voice `index`, through `synth` if that is the selected voice, which the caller already holds.
+fn with_voice (index: usize, synth: &mut Synth, action: impl FnOnce(&mut Synth)) {
+    if index == selected_voice() { action(synth); }
+    else if let Some(voice) = voices().get(index) { action(&mut voice.lock().unwrap()); }
+}
+
+/// Runs `action` on every voice but the selected one, which the caller already holds.
+fn with_other_voices (mut action: impl FnMut(&mut Synth)) {
+    for (_, voice) in voices().iter().enumerate().filter(|(i, _)| *i != selected_voice()) {
+        action(&mut voice.lock().unwrap());
     }
 }
 
-pub fn process_keyboard_events(seq: Arc<Mutex<Sequencer>>) -> impl Fn(Event) {
+pub fn process_keyboard_events(seq: Arc<Mutex<Sequencer>>) -> impl FnMut(Event) {
+    let mut shift_layer = false;
+    let mut control_layer = false;
     move |event: Event| {
         match event.event_type {
             KeyPress(_) | KeyRelease(_) => (),
             _ => return
         }
+        match event.event_type {
+            KeyPress(Key::ShiftRight) => shift_layer = true,
+            KeyRelease(Key::ShiftRight) => shift_layer = false,
+            KeyPress(Key::ControlRight) => control_layer = true,
+            KeyRelease(Key::ControlRight) => control_layer = false,
+            _ => (),
+        }
 
         let mut seq = seq.lock().unwrap();
-        let mut synth = VOICES.get(SELECTED_VOICE).unwrap().lock().unwrap();
+        match event.event_type {
+            KeyPress(Key::Equal) if !control_layer => {
+                // keys still held would never be released on the voice that is left
+                voices().get(selected_voice()).unwrap().lock().unwrap().release_keys();
+                select_voice(if shift_layer { -1 } else { 1 });
+            },
+            KeyPress(key @ (Key::UpArrow | Key::DownArrow)) if control_layer => {
+                let count = if key == Key::UpArrow { voices().len() + 1 } else { voices().len().saturating_sub(1) };
+                if let Some(removed) = voices().get(count) { removed.lock().unwrap().release_keys(); }
+                set_voice_count(count);
+                if selected_voice() >= voices().len() { select_voice(-1); }
+            },
+            _ => (),
+        }
+        let mut synth = voices().get(selected_voice()).unwrap().lock().unwrap();
+        let before = matches!(event.event_type, KeyPress(key) if key != Key::BackQuote).then(|| (synth.snapshot(), seq.snapshot()));
 
         match event.event_type {
+            KeyPress(key) if seq.tempo_entry.is_some() && key2digit(key).is_some()
+            => seq.tempo_entry_digit(key2digit(key).unwrap()),
+            KeyPress(Key::Backspace) if seq.tempo_entry.is_some() => seq.tempo_entry_delete(),
+            KeyPress(Key::Return) if seq.tempo_entry.is_some() => seq.confirm_tempo_entry(),
+            KeyPress(Key::Return) if shift_layer => seq.start_tempo_entry(),
+            KeyPress(Key::Return) => seq.tap_tempo(),
+            KeyPress(key) if KEY_NOTES.contains_key(&key) && seq.voice_allocator.is_poly() => {
+                let note = *KEY_NOTES.get(&key).unwrap();
+                let (voice, stolen) = seq.voice_allocator.note_on(note, voices().len());
+                with_voice(voice, &mut synth, |voice| {
+                    if let Some(stolen) = stolen { voice.note_off(stolen); }
+                    voice.note_on(note, shift_layer);
+                });
+            },
+            KeyRelease(key) if KEY_NOTES.contains_key(&key) && seq.voice_allocator.is_poly() => {
+                let note = *KEY_NOTES.get(&key).unwrap();
+                if let Some(voice) = seq.voice_allocator.note_off(note) {
+                    with_voice(voice, &mut synth, |voice| voice.note_off(note));
+                }
+            },
+            KeyPress(key) if KEY_NOTES.contains_key(&key) && seq.key_zones.is_on() => {
+                let note = *KEY_NOTES.get(&key).unwrap();
+                for (voice, note) in seq.key_zones.note_on(note, selected_voice(), voices().len()) {
+                    with_voice(voice, &mut synth, |voice| voice.note_on(note, shift_layer));
+                }
+            },
+            KeyRelease(key) if KEY_NOTES.contains_key(&key) && seq.key_zones.is_on() => {
+                let note = *KEY_NOTES.get(&key).unwrap();
+                for (voice, note) in seq.key_zones.note_off(note) {
+                    with_voice(voice, &mut synth, |voice| voice.note_off(note));
+                }
+            },
             KeyPress(key) if KEY_NOTES.contains_key(&key)
-            => synth.note_on(*KEY_NOTES.get(&key).unwrap()),
+            => synth.note_on(*KEY_NOTES.get(&key).unwrap(), shift_layer),
             KeyRelease(key) if KEY_NOTES.contains_key(&key)
             => synth.note_off(*KEY_NOTES.get(&key).unwrap()),
 
+            KeyPress(key) if control_layer && key2voice(key).is_some() => {
+                with_voice(key2voice(key).unwrap(), &mut synth, |voice| 
+                    if shift_layer { voice.toggle_solo() } else { voice.toggle_mute() }
+                );
+            },
+            // the function keys only reach the first twelve voices
+            KeyPress(Key::Num0) if control_layer => if shift_layer { synth.toggle_solo() } else { synth.toggle_mute() },
+            KeyPress(Key::F1) if shift_layer => synth.volume_down(),
+            KeyPress(Key::F2) if shift_layer => synth.volume_up(),
+            KeyPress(Key::F3) if shift_layer => synth.pan_left(),
+            KeyPress(Key::F4) if shift_layer => synth.pan_right(),
+            KeyPress(Key::F5) if shift_layer => synth.balance_down(),
+            KeyPress(Key::F6) if shift_layer => synth.balance_up(),
+            KeyPress(Key::F7) if shift_layer => synth.lpg_colour_down(),
+            KeyPress(Key::F8) if shift_layer => synth.lpg_colour_up(),
             KeyPress(Key::F1) => synth.model_down(),
             KeyPress(Key::F2) => synth.model_up(),
             KeyPress(Key::F3) => synth.harmonic_down(),
@@ -94,13 +350,22 @@ pub fn process_keyboard_events(seq: Arc<Mutex<Sequencer>>) -> impl Fn(Event) {
             KeyPress(Key::F6) => synth.timbre_up(),
             KeyPress(Key::F7) => synth.morph_down(),
             KeyPress(Key::F8) => synth.morph_up(),
+            KeyPress(Key::F9) if shift_layer => synth.glide_down(),
+            KeyPress(Key::F10) if shift_layer => synth.glide_up(),
             KeyPress(Key::F9) => synth.decay_down(),
             KeyPress(Key::F10) => synth.decay_up(),
+            KeyPress(Key::Slash) if control_layer => synth.next_note_priority(),
+            KeyPress(Key::Slash) if shift_layer => synth.toggle_legato(),
+            KeyPress(Key::Slash) => synth.toggle_slide(),
             KeyPress(Key::ShiftLeft) => synth.pitch_bend_negative(),
             KeyPress(Key::IntlBackslash) => synth.pitch_bend_positive(),
             KeyRelease(Key::ShiftLeft | Key::IntlBackslash) => synth.pitch_bend_neutral(),
             KeyPress(Key::ControlLeft) => synth.vibrato_on(),
             KeyRelease(Key::ControlLeft) => synth.vibrato_off(),
+            KeyPress(Key::LeftArrow) if control_layer => seq.key_zones.interval_down(),
+            KeyPress(Key::RightArrow) if control_layer => seq.key_zones.interval_up(),
+            KeyPress(Key::LeftArrow) if shift_layer => synth.nudge_step_earlier(),
+            KeyPress(Key::RightArrow) if shift_layer => synth.nudge_step_later(),
             KeyPress(Key::LeftArrow) => synth.gate_length_down(),
             KeyPress(Key::RightArrow) => synth.gate_length_up(),
             KeyPress(Key::Dot) => synth.transpose_down(),
@@ -108,26 +373,138 @@ pub fn process_keyboard_events(seq: Arc<Mutex<Sequencer>>) -> impl Fn(Event) {
             KeyPress(Key::LeftBracket) => synth.add_rest(),
             KeyPress(Key::RightBracket) => synth.start_recording_or_undo_last(&mut seq),
             KeyPress(Key::Backspace) => synth.clear_notes(),
-            KeyPress(Key::Space) => seq.play_pause(),
+            KeyPress(Key::Space) => {
+                seq.play_pause();
+                synth.seq_status = seq.status;
+            },
+            KeyPress(Key::F11) if shift_layer => seq.locate_previous_bar(),
+            KeyPress(Key::F12) if shift_layer => seq.locate_next_bar(),
+            KeyPress(Key::F11) => {
+                seq.stop();
+                synth.seq_status = seq.status;
+            },
+            KeyPress(Key::F12) => {
+                seq.start();
+                synth.seq_status = seq.status;
+            },
+            KeyPress(Key::Num0) => {
+                seq.toggle_overdub();
+                synth.seq_status = seq.status;
+            },
+            KeyPress(Key::KeyP) if shift_layer => seq.count_in_bars_cycle(),
+            KeyPress(Key::KeyP) => seq.toggle_metronome(),
+            KeyPress(Key::KeyL) => seq.next_time_signature(),
+            KeyPress(Key::Tab) => synth.toggle_record_mode(),
+            KeyPress(Key::Home) => synth.toggle_step_editor(),
+            KeyPress(Key::PageUp) if shift_layer => synth.set_loop_start(),
+            KeyPress(Key::PageDown) if shift_layer => synth.set_loop_end(),
+            KeyPress(Key::SemiColon) if shift_layer => synth.clear_loop(),
+            KeyPress(Key::SemiColon) => synth.next_direction(),
+            KeyPress(Key::PageUp) => synth.cursor_prev(),
+            KeyPress(Key::PageDown) => synth.cursor_next(),
+            KeyPress(Key::Insert) => synth.insert_step(),
+            KeyPress(Key::Delete) => synth.delete_step(),
+            KeyPress(Key::End) => synth.duplicate_step(),
+            KeyPress(Key::BackSlash) if shift_layer => synth.cycle_step_condition(),
+            KeyPress(Key::BackSlash) => synth.clear_step_locks(),
+            KeyPress(Key::Quote) => seq.is_filling = true,
+            KeyRelease(Key::Quote) => seq.is_filling = false,
+            KeyPress(Key::Kp4) => synth.rotate_pattern_left(),
+            KeyPress(Key::Kp6) => synth.rotate_pattern_right(),
+            KeyPress(Key::Kp8) => synth.reverse_pattern(),
+            KeyPress(Key::Kp2) => synth.invert_pattern(),
+            KeyPress(Key::Kp5) => synth.retrograde_invert_pattern(),
+            KeyPress(Key::KpMultiply) => synth.stretch_pattern(),
+            KeyPress(Key::KpDivide) => synth.shrink_pattern(),
+            KeyPress(Key::Kp0) => synth.shuffle_pattern(),
+            KeyPress(Key::Kp7) if shift_layer => {
+                let mut patterns = vec![synth.seq_notes.clone()];
+                with_other_voices(|voice| patterns.push(voice.seq_notes.clone()));
+                synth.generate_pattern(&patterns);
+            },
+            KeyPress(Key::Kp7) => {
+                let pattern = synth.seq_notes.clone();
+                synth.generate_pattern(&[pattern]);
+            },
+            KeyPress(Key::KeyQ) if shift_layer => {
+                synth.store_scene(0);
+                with_other_voices(|voice| voice.store_scene(0));
+            },
+            KeyPress(Key::KeyA) if shift_layer => {
+                synth.store_scene(1);
+                with_other_voices(|voice| voice.store_scene(1));
+            },
+            KeyPress(Key::KeyQ) => {
+                synth.scene_morph_to_a();
+                with_other_voices(|voice| voice.scene_morph_to_a());
+            },
+            KeyPress(Key::KeyA) => {
+                synth.scene_morph_to_b();
+                with_other_voices(|voice| voice.scene_morph_to_b());
+            },
+            KeyPress(Key::KpReturn) if control_layer => {
+                synth.release_keys();
+                with_other_voices(|voice| voice.release_keys());
+                seq.key_zones.next_mode();
+            },
+            KeyPress(Key::KpReturn) => {
+                synth.release_keys();
+                with_other_voices(|voice| voice.release_keys());
+                seq.voice_allocator.next_mode();
+            },
+            KeyPress(Key::Equal) if control_layer => seq.key_zones.next_voice(voices().len()),
+            KeyPress(Key::Kp9) if shift_layer => synth.freeze_turing(),
+            KeyPress(Key::Kp9) => synth.toggle_turing(),
+            KeyPress(Key::Kp1) if shift_layer => synth.turing_length_down(),
+            KeyPress(Key::Kp3) if shift_layer => synth.turing_length_up(),
+            KeyPress(Key::Kp1) => synth.turing_lock_down(),
+            KeyPress(Key::Kp3) => synth.turing_lock_up(),
+            KeyPress(Key::KpMinus) => synth.generator_length_down(),
+            KeyPress(Key::KpPlus) => synth.generator_length_up(),
+            KeyPress(Key::Num1) if control_layer => synth.velocity_down(),
+            KeyPress(Key::Num2) if control_layer => synth.velocity_up(),
+            KeyPress(Key::Num1) if shift_layer => seq.humanize_down(),
+            KeyPress(Key::Num2) if shift_layer => seq.humanize_up(),
+            KeyPress(Key::Num1) => synth.quantize_down(),
+            KeyPress(Key::Num2) => synth.quantize_up(),
+            KeyPress(Key::UpArrow | Key::DownArrow) if control_layer => (),
+            KeyPress(Key::DownArrow) if shift_layer => seq.tempo_ramp_bars_down(),
+            KeyPress(Key::UpArrow) if shift_layer => seq.tempo_ramp_bars_up(),
             KeyPress(Key::DownArrow) => Sequencer::tempo_down(&mut seq),
             KeyPress(Key::UpArrow) => Sequencer::tempo_up(&mut seq),
+            KeyPress(Key::BackQuote) if shift_layer => synth.redo(&mut seq),
+            KeyPress(Key::BackQuote) => synth.undo(&mut seq),
             // KeyPress(key) => println!("{:?}", key),
-            // Not implemented: self.balance, self.volume, self.patch.lpg_colour
             _ => {}
         }
+        if let Some((before, seq_before)) = before {
+            synth.commit_history(before);
+            seq.commit_history(seq_before);
+        }
         // Print Info
-        match event.event_type {
+        let info = match event.event_type {
+            KeyPress(_) if seq.tempo_entry.is_some() => Some(format_info(&seq, &synth)),
             KeyPress(
-                Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6 | Key::F7 | Key::F8 | Key::F9 | Key::F10 |
-                Key::Space | Key::RightBracket | Key::UpArrow | Key::DownArrow | Key::Dot | Key::Minus | Key::LeftArrow | Key::RightArrow
-            ) => {
-                print_info(&seq, &synth);
-            },
-            KeyPress(key) if KEY_NOTES.contains_key(&key) && seq.is_recording() => print_info(&seq, &synth),
-            KeyPress(Key::LeftBracket | Key::Backspace) if seq.is_recording() => print_info(&seq, &synth),
+                Key::F1 | Key::F2 | Key::F3 | Key::F4 | Key::F5 | Key::F6 | Key::F7 | Key::F8 | Key::F9 | Key::F10 | Key::F11 | Key::F12 |
+                Key::Space | Key::Return | Key::RightBracket | Key::UpArrow | Key::DownArrow | Key::Dot | Key::Minus | Key::LeftArrow | Key::RightArrow |
+                Key::Tab | Key::Num0 | Key::Num1 | Key::Num2 | Key::Slash |
+                Key::Home | Key::PageUp | Key::PageDown | Key::Insert | Key::Delete | Key::End | Key::BackSlash |
+                Key::Kp4 | Key::Kp6 | Key::Kp8 | Key::Kp2 | Key::Kp5 | Key::KpMultiply | Key::KpDivide | Key::Kp0 |
+                Key::Kp7 | Key::KpMinus | Key::KpPlus | Key::Kp9 | Key::Kp1 | Key::Kp3 | Key::KeyQ | Key::KeyA | Key::BackQuote |
+                Key::KeyP | Key::KeyL | Key::Quote | Key::SemiColon | Key::Equal | Key::KpReturn
+            ) => Some(format_info(&seq, &synth)),
+            KeyPress(key) if KEY_NOTES.contains_key(&key) && (seq.is_recording() || seq.is_overdubbing()) => Some(format_info(&seq, &synth)),
+            KeyPress(Key::LeftBracket | Key::Backspace) if seq.is_recording() || seq.is_overdubbing() => Some(format_info(&seq, &synth)),
+            KeyPress(key) if (KEY_NOTES.contains_key(&key) || key == Key::LeftBracket || key == Key::Backspace) && synth.is_editing() 
+            => Some(format_info(&seq, &synth)),
+            KeyRelease(Key::Quote) => Some(format_info(&seq, &synth)),
             KeyPress(Key::Escape) => std::process::exit(0),
-            _ => ()
-        }
+            _ => None,
+        };
+        // the audio thread waits on the sequencer for every block, so the screen is printed once the locks are let go
+        drop(synth);
+        drop(seq);
+        if let Some(info) = info { print_screen(&info); }
     }
 }
 